@@ -0,0 +1,36 @@
+//! Record the versions of key dependencies for `testsearch version --verbose`
+
+use std::{env, fs, path::Path};
+
+/// Dependencies whose versions are reported, and the environment variable each is exposed as
+const DEPENDENCIES: &[(&str, &str)] = &[
+    ("skim", "TESTSEARCH_SKIM_VERSION"),
+    ("tree-sitter", "TESTSEARCH_TREE_SITTER_VERSION"),
+    (
+        "tree-sitter-python",
+        "TESTSEARCH_TREE_SITTER_PYTHON_VERSION",
+    ),
+];
+
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is set by cargo");
+    let lock_file = Path::new(&manifest_dir).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock_file.display());
+
+    let lock = fs::read_to_string(&lock_file).unwrap_or_default();
+    for (name, var) in DEPENDENCIES {
+        let version = locked_version(&lock, name).unwrap_or("unknown");
+        println!("cargo:rustc-env={var}={version}");
+    }
+}
+
+/// Find the version of the first package called `name` in the contents of a `Cargo.lock`
+fn locked_version<'a>(lock: &'a str, name: &str) -> Option<&'a str> {
+    let name_line = format!("name = \"{name}\"");
+    let mut lines = lock.lines();
+    lines.find(|line| *line == name_line)?;
+    lines
+        .next()?
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+}
@@ -0,0 +1,38 @@
+# Expected node ids:
+#   test_async_tests.py::test_module_level
+#   test_async_tests.py::test_marked
+#   test_async_tests.py::test_parametrized[1]
+#   test_async_tests.py::test_parametrized[2]
+#   test_async_tests.py::TestAsync::test_method
+#   test_async_tests.py::TestAsync::TestNested::test_nested
+import pytest
+
+
+async def test_module_level():
+    pass
+
+
+@pytest.mark.asyncio
+async def test_marked():
+    pass
+
+
+@pytest.mark.parametrize("value", [1, 2])
+async def test_parametrized(value):
+    pass
+
+
+async def helper():
+    pass
+
+
+class TestAsync:
+    async def test_method(self):
+        pass
+
+    async def helper(self):
+        pass
+
+    class TestNested:
+        async def test_nested(self):
+            pass
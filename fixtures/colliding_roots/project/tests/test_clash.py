@@ -0,0 +1,2 @@
+def test_in_rootdir():
+    pass
@@ -0,0 +1,55 @@
+# Tests defined inside compound statements, only found with `--deep`.
+#
+# Expected node ids by default:
+#   test_conditional_tests.py::test_always
+#
+# Expected node ids with `--deep`:
+#   test_conditional_tests.py::test_always
+#   test_conditional_tests.py::test_new_python
+#   test_conditional_tests.py::test_old_python
+#   test_conditional_tests.py::test_with_numpy
+#   test_conditional_tests.py::test_without_numpy
+#   test_conditional_tests.py::TestPlatform::test_other
+#   test_conditional_tests.py::TestPlatform::test_posix
+import os
+import sys
+
+
+def test_always():
+    pass
+
+
+if sys.version_info >= (3, 12):
+
+    def test_new_python():
+        pass
+
+else:
+
+    def test_old_python():
+        pass
+
+
+try:
+    import numpy  # noqa: F401
+except ImportError:
+
+    def test_without_numpy():
+        pass
+
+else:
+
+    def test_with_numpy():
+        pass
+
+
+class TestPlatform:
+    if os.name == "posix":
+
+        def test_posix(self):
+            pass
+
+    else:
+
+        def test_other(self):
+            pass
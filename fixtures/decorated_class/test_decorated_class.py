@@ -0,0 +1,40 @@
+# Expected node ids, with the marks each test carries:
+#   test_decorated_class.py::TestSkipped::test_inherits_skip        [skip]
+#   test_decorated_class.py::TestSkipped::test_own_mark             [skip, slow]
+#   test_decorated_class.py::TestSkipped::TestNested::test_nested   [skip, network]
+#   test_decorated_class.py::TestSlow::test_slow                    [usefixtures, slow]
+#   test_decorated_class.py::test_plain                             []
+import pytest
+from pytest import mark
+
+
+@pytest.mark.skip(reason="demonstrates class-level marks")
+class TestSkipped:
+    def test_inherits_skip(self):
+        pass
+
+    @pytest.mark.slow
+    def test_own_mark(self):
+        pass
+
+    @mark.network
+    class TestNested:
+        def test_nested(self):
+            pass
+
+
+@pytest.mark.usefixtures("tmp_path")
+@pytest.mark.slow
+class TestSlow:
+    def test_slow(self):
+        pass
+
+
+@pytest.mark.skip
+class Helper:
+    def test_not_collected(self):
+        pass
+
+
+def test_plain():
+    pass
@@ -0,0 +1,40 @@
+# Expected docstrings, as shown by `--format json` and the fuzzy finder preview:
+#   test_docstrings.py::test_one_line                  "Retries use exponential backoff."
+#   test_docstrings.py::test_multi_line                "Summary line.\n\nDetails, indented\n  relative to the body."
+#   test_docstrings.py::test_after_comment             "Comments before the docstring are skipped."
+#   test_docstrings.py::test_no_docstring              null
+#   test_docstrings.py::test_not_first_statement       null
+#   test_docstrings.py::TestClass::test_method         "Method docstrings work too."
+
+
+def test_one_line():
+    """Retries use exponential backoff."""
+
+
+def test_multi_line():
+    """
+    Summary line.
+
+    Details, indented
+      relative to the body.
+    """
+
+
+def test_after_comment():
+    # a comment is not a statement
+    """Comments before the docstring are skipped."""
+
+
+def test_no_docstring():
+    assert True
+
+
+def test_not_first_statement():
+    value = 1
+    """Only the first statement can be a docstring."""
+    assert value
+
+
+class TestClass:
+    def test_method(self):
+        'Method docstrings work too.'
@@ -0,0 +1,14 @@
+﻿# Expected node ids:
+#   test_bom.py::test_after_bom
+#   test_bom.py::TestBom::test_method
+#
+# The file starts with a UTF-8 byte order mark, as some Windows editors write.
+
+
+def test_after_bom():
+    assert "café" == "café"
+
+
+class TestBom:
+    def test_method(self):
+        pass
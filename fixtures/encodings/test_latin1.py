@@ -0,0 +1,15 @@
+# -*- coding: latin-1 -*-
+# Expected node ids:
+#   test_latin1.py::test_accented_string
+#   test_latin1.py::TestCaf::test_method
+#
+# The file is encoded as Latin-1, so it is not valid UTF-8.
+
+
+def test_accented_string():
+    assert "nave" != "naive"
+
+
+class TestCaf:
+    def test_method(self):
+        pass
@@ -0,0 +1,6 @@
+# -*- coding: shift_jis -*-
+# Expected node ids: none, the file is skipped with a warning as Shift JIS is not supported.
+
+
+def test_unsupported():
+    assert "{"
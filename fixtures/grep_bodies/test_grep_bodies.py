@@ -0,0 +1,26 @@
+# Expected node ids:
+#   test_grep_bodies.py::test_get_only
+#   test_grep_bodies.py::test_assert_only
+#   test_grep_bodies.py::test_get_and_assert
+#   test_grep_bodies.py::test_neither
+#
+# Used by tests/grep.rs, which greps these bodies for `requests.get` and `assert_called`.
+
+import requests
+
+
+def test_get_only():
+    requests.get("https://example.com")
+
+
+def test_assert_only(mock):
+    mock.assert_called()
+
+
+def test_get_and_assert(mock):
+    requests.get("https://example.com")
+    mock.assert_called()
+
+
+def test_neither():
+    assert 1 + 1 == 2
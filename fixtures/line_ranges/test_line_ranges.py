@@ -0,0 +1,38 @@
+# Expected node ids with --format line-range, spanning decorators and the whole body:
+#   test_line_ranges.py::test_one_line@14-14
+#   test_line_ranges.py::test_decorated@17-21
+#   test_line_ranges.py::test_parametrized[1]@24-26
+#   test_line_ranges.py::test_parametrized[2]@24-26
+#   test_line_ranges.py::TestClass::test_method@30-34
+#   test_line_ranges.py::TestClass::test_after_decorator@36-38
+import pytest
+
+
+def helper():
+    pass
+
+def test_one_line(): pass
+
+
+@pytest.mark.slow
+def test_decorated():
+    x = 1
+
+    assert x == 1
+
+
+@pytest.mark.parametrize("value", [1, 2])
+def test_parametrized(value):
+    assert value
+
+
+class TestClass:
+    def test_method(self):
+        assert (
+            1
+            == 1
+        )
+
+    @pytest.mark.skip
+    def test_after_decorator(self):
+        pass
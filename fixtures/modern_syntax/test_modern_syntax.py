@@ -0,0 +1,58 @@
+# Module and class bodies containing statements that cannot define tests, which are skipped.
+#
+# Expected node ids:
+#   test_modern_syntax.py::test_after_match
+#   test_modern_syntax.py::TestWithStatements::test_method
+import os
+import sys
+
+type Number = int | float
+
+match sys.platform:
+    case "linux":
+        PLATFORM = "linux"
+    case _:
+        PLATFORM = "other"
+
+with open(os.devnull) as devnull:
+    pass
+
+for name in ["a", "b"]:
+    pass
+
+while False:
+    pass
+
+counter = 0
+del counter
+
+global_value = 1
+
+
+def helper():
+    global global_value
+    return global_value
+
+
+def test_after_match():
+    match PLATFORM:
+        case "linux":
+            assert True
+        case _:
+            assert True
+
+
+class TestWithStatements:
+    match sys.platform:
+        case "linux":
+            attribute = 1
+        case _:
+            attribute = 2
+
+    for index in range(2):
+        pass
+
+    del index
+
+    def test_method(self):
+        assert self.attribute
@@ -0,0 +1,32 @@
+# Expected node ids with `--class-pattern '^Describe' --function-pattern '^(should|spec)_'`:
+#   test_naming_conventions.py::should_add
+#   test_naming_conventions.py::spec_subtract
+#   test_naming_conventions.py::DescribeCalculator::should_multiply
+#
+# With the default patterns only `test_naming_conventions.py::test_default` is found.
+
+
+def should_add():
+    assert 1 + 1 == 2
+
+
+def spec_subtract():
+    assert 2 - 1 == 1
+
+
+def test_default():
+    pass
+
+
+def helper_not_a_test():
+    pass
+
+
+class DescribeCalculator:
+    def should_multiply(self):
+        assert 2 * 3 == 6
+
+
+class TestIgnoredByCustomPattern:
+    def should_divide(self):
+        assert 6 / 3 == 2
@@ -0,0 +1,26 @@
+# Expected node ids:
+#   test_nested_classes.py::TestOuter::test_outer
+#   test_nested_classes.py::TestOuter::TestMiddle::test_middle
+#   test_nested_classes.py::TestOuter::TestMiddle::TestInner::test_inner
+#   test_nested_classes.py::TestOuter::test_after_nested
+
+
+class TestOuter:
+    def test_outer(self):
+        pass
+
+    class TestMiddle:
+        def test_middle(self):
+            pass
+
+        class TestInner:
+            def test_inner(self):
+                pass
+
+    class Helper:
+        class TestHidden:
+            def test_hidden(self):
+                pass
+
+    def test_after_nested(self):
+        pass
@@ -0,0 +1,14 @@
+# Expected node ids with --relative-to-rootdir, from any search root inside this directory:
+#   tests/unit/test_core.py::test_answer
+#   tests/unit/test_core.py::TestAnswer::test_is_int
+
+from pkg.core import answer
+
+
+def test_answer():
+    assert answer() == 42
+
+
+class TestAnswer:
+    def test_is_int(self):
+        assert isinstance(answer(), int)
@@ -0,0 +1,67 @@
+# Expected node ids:
+#   test_parametrize.py::test_implicit_ids[1]
+#   test_parametrize.py::test_implicit_ids[-2]
+#   test_parametrize.py::test_implicit_ids[3.5]
+#   test_parametrize.py::test_implicit_ids[text]
+#   test_parametrize.py::test_implicit_ids[True]
+#   test_parametrize.py::test_implicit_ids[None]
+#   test_parametrize.py::test_param_ids[1-2]
+#   test_parametrize.py::test_param_ids[explicit]
+#   test_parametrize.py::test_param_ids[5-6]
+#   test_parametrize.py::test_param_ids[a3-x]
+#   test_parametrize.py::test_ids_keyword[first]
+#   test_parametrize.py::test_ids_keyword[3-4]
+#   test_parametrize.py::test_duplicate_ids[1_0]
+#   test_parametrize.py::test_duplicate_ids[1_1]
+#   test_parametrize.py::test_duplicate_ids[a0]
+#   test_parametrize.py::test_duplicate_ids[a1]
+#   test_parametrize.py::test_from_variable          (values not known from the source)
+#   test_parametrize.py::TestClass::test_method[off]
+#   test_parametrize.py::TestClass::test_method[True]
+import pytest
+
+
+class Point:
+    pass
+
+
+@pytest.mark.parametrize("value", [1, -2, 3.5, "text", True, None])
+def test_implicit_ids(value):
+    pass
+
+
+@pytest.mark.parametrize(
+    "a, b",
+    [
+        (1, 2),
+        pytest.param(3, 4, id="explicit"),
+        pytest.param(5, 6),
+        (Point(), "x"),
+    ],
+)
+def test_param_ids(a, b):
+    pass
+
+
+@pytest.mark.parametrize(("x", "y"), [(1, 2), (3, 4)], ids=["first", None])
+def test_ids_keyword(x, y):
+    pass
+
+
+@pytest.mark.parametrize("n", [1, 1, "a", "a"])
+def test_duplicate_ids(n):
+    pass
+
+
+CASES = [1, 2]
+
+
+@pytest.mark.parametrize("n", CASES)
+def test_from_variable(n):
+    pass
+
+
+class TestClass:
+    @pytest.mark.parametrize("flag", [pytest.param(False, id="off"), True])
+    def test_method(self, flag):
+        pass
@@ -0,0 +1,35 @@
+# Expected node ids, the decorator closest to the function first:
+#   test_stacked_parametrize.py::test_stacked[2-0]
+#   test_stacked_parametrize.py::test_stacked[2-1]
+#   test_stacked_parametrize.py::test_stacked[3-0]
+#   test_stacked_parametrize.py::test_stacked[3-1]
+#   test_stacked_parametrize.py::test_stacked_ids[fast-small]
+#   test_stacked_parametrize.py::test_stacked_ids[fast-large]
+#   test_stacked_parametrize.py::test_stacked_ids[slow-small]
+#   test_stacked_parametrize.py::test_stacked_ids[slow-large]
+#   test_stacked_parametrize.py::test_indirect[a]
+#   test_stacked_parametrize.py::test_indirect[b]
+# With --max-parametrize 3, the stacked tests are shown by their bare names.
+import pytest
+
+
+@pytest.mark.parametrize("x", [0, 1])
+@pytest.mark.parametrize("y", [2, 3])
+def test_stacked(x, y):
+    pass
+
+
+@pytest.mark.parametrize("size", [pytest.param(1, id="small"), pytest.param(2, id="large")])
+@pytest.mark.parametrize("speed", ["fast", "slow"])
+def test_stacked_ids(size, speed):
+    pass
+
+
+@pytest.fixture
+def letter(request):
+    return request.param
+
+
+@pytest.mark.parametrize("letter", ["a", "b"], indirect=True)
+def test_indirect(letter):
+    pass
@@ -0,0 +1,20 @@
+# Expected node ids:
+#   test_python2.py::test_print
+#   test_python2.py::TestLegacy::test_exec
+#
+# Python 2 `print` and `exec` statements at module and class level are skipped.
+
+print "collecting"
+exec "x = 1"
+
+
+def test_print():
+    print "hello"
+
+
+class TestLegacy:
+    print "class body"
+    exec "y = 2" in {}
+
+    def test_exec(self):
+        exec "z = 3"
@@ -0,0 +1,9 @@
+# Expected node ids with --relative-to-rootdir:
+#   tests/test_fallback.py::test_found
+#
+# No directory above configures pytest or has a setup.py, so the pyproject.toml without a pytest
+# section decides the rootdir.
+
+
+def test_found():
+    pass
@@ -0,0 +1,9 @@
+# Expected node ids with --relative-to-rootdir:
+#   project/tests/test_sections.py::test_found
+#
+# The pyproject.toml and setup.cfg next to `tests` have no pytest section, so pytest takes the
+# directory of the tox.ini above them, which does, as its rootdir.
+
+
+def test_found():
+    pass
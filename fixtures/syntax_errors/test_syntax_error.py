@@ -0,0 +1,23 @@
+# Expected node ids:
+#   test_syntax_error.py::test_before_error
+#   test_syntax_error.py::test_after_error
+#   test_syntax_error.py::TestAfterError::test_method
+#
+# The stray `)` at module level is a syntax error, which tree-sitter reports as an ERROR node
+# between the tests. The tests around it are still found.
+
+
+def test_before_error():
+    pass
+
+
+x = )
+
+
+def test_after_error():
+    pass
+
+
+class TestAfterError:
+    def test_method(self):
+        pass
@@ -0,0 +1,47 @@
+# Expected node ids, for unittest.TestCase subclasses whatever their names:
+#   test_unittest_style.py::FooTests::test_value
+#   test_unittest_style.py::BarTests::test_bar
+#   test_unittest_style.py::AsyncTests::test_async_case
+#   test_unittest_style.py::DjangoStyleTests::test_django
+import unittest
+from unittest import TestCase
+
+
+class FooTests(unittest.TestCase):
+    def setUp(self):
+        self.value = 1
+
+    def tearDown(self):
+        self.value = None
+
+    def test_value(self):
+        self.assertEqual(self.value, 1)
+
+    def helper(self):
+        pass
+
+
+class BarTests(TestCase):
+    def test_bar(self):
+        pass
+
+
+class AsyncTests(unittest.IsolatedAsyncioTestCase):
+    def test_async_case(self):
+        pass
+
+
+class NotATest:
+    def test_ignored(self):
+        pass
+
+
+# only the exact unittest base names count, not names which merely end the same way
+class HelperTests(MyTestCase):
+    def test_ignored(self):
+        pass
+
+
+class DjangoStyleTests(django.test.TestCase):
+    def test_django(self):
+        pass
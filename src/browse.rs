@@ -0,0 +1,226 @@
+//! `testsearch browse`: navigate the discovered tests as a collapsible tree of files, classes and
+//! tests, as an alternative to the flat fuzzy finder
+
+use std::{io, sync::Arc};
+
+use color_eyre::eyre::{self, Context};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    Terminal,
+    backend::CrosstermBackend,
+    style::{Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState},
+};
+use skim::prelude::SkimItem;
+
+use crate::TestCase;
+
+/// A file, class or test in the tree
+struct TreeNode {
+    label: String,
+    /// Node id selecting this test, or every test below this file or class
+    node_id: String,
+    children: Vec<TreeNode>,
+    expanded: bool,
+}
+
+impl TreeNode {
+    fn new(label: impl Into<String>, node_id: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            node_id: node_id.into(),
+            children: Vec::new(),
+            expanded: false,
+        }
+    }
+
+    /// The child called `label`, added with `node_id` if there is none yet
+    fn child(&mut self, label: &str, node_id: &str) -> &mut TreeNode {
+        let index = match self.children.iter().position(|child| child.label == label) {
+            Some(index) => index,
+            None => {
+                self.children.push(TreeNode::new(label, node_id));
+                self.children.len() - 1
+            }
+        };
+        &mut self.children[index]
+    }
+}
+
+/// Group `tests`, in order, by file and then by class
+fn build_tree(tests: &[Arc<dyn SkimItem>]) -> Vec<TreeNode> {
+    let mut root = TreeNode::new("", "");
+    for test in tests {
+        let node_id = test.output();
+        let Some(test_case) = test.as_any().downcast_ref::<TestCase>() else {
+            root.children
+                .push(TreeNode::new(node_id.as_ref(), node_id.as_ref()));
+            continue;
+        };
+
+        let file = test_case
+            .id_file
+            .as_ref()
+            .unwrap_or(&test_case.file)
+            .display()
+            .to_string();
+        let mut parent = root.child(&file, &file);
+
+        // notebook tests are selected by cell, which has no classes
+        if test_case.cell.is_some() {
+            let label = node_id
+                .strip_prefix(&format!("{file}::"))
+                .unwrap_or(&node_id);
+            parent.child(label, &node_id);
+            continue;
+        }
+
+        let mut class_id = file;
+        for class_name in &test_case.classes {
+            class_id = format!("{class_id}::{class_name}");
+            parent = parent.child(class_name, &class_id);
+        }
+        parent.child(&test_case.item_name(), &node_id);
+    }
+    root.children
+}
+
+/// The visible nodes of the tree, in display order, each given by its index among its siblings
+/// at every depth
+fn visible_rows(nodes: &[TreeNode]) -> Vec<Vec<usize>> {
+    fn visit(nodes: &[TreeNode], path: &mut Vec<usize>, rows: &mut Vec<Vec<usize>>) {
+        for (index, node) in nodes.iter().enumerate() {
+            path.push(index);
+            rows.push(path.clone());
+            if node.expanded {
+                visit(&node.children, path, rows);
+            }
+            path.pop();
+        }
+    }
+
+    let mut rows = Vec::new();
+    visit(nodes, &mut Vec::new(), &mut rows);
+    rows
+}
+
+fn node_at<'a>(nodes: &'a [TreeNode], path: &[usize]) -> &'a TreeNode {
+    let (first, rest) = path.split_first().expect("row paths are never empty");
+    rest.iter()
+        .fold(&nodes[*first], |node, index| &node.children[*index])
+}
+
+fn node_at_mut<'a>(nodes: &'a mut [TreeNode], path: &[usize]) -> &'a mut TreeNode {
+    let (first, rest) = path.split_first().expect("row paths are never empty");
+    rest.iter()
+        .fold(&mut nodes[*first], |node, index| &mut node.children[*index])
+}
+
+/// Show `tests` as a tree under `title`, returning the node id of the selected file, class or
+/// test, or `None` if the user quit
+pub fn browse(tests: &[Arc<dyn SkimItem>], title: &str) -> eyre::Result<Option<String>> {
+    let mut nodes = build_tree(tests);
+    // a single file is not worth collapsing
+    if let [node] = nodes.as_mut_slice() {
+        node.expanded = true;
+    }
+
+    terminal::enable_raw_mode().wrap_err("enabling raw mode")?;
+    execute!(io::stderr(), EnterAlternateScreen).wrap_err("entering alternate screen")?;
+
+    let result = run(&mut nodes, title);
+
+    // restore the terminal even if drawing failed
+    terminal::disable_raw_mode().wrap_err("disabling raw mode")?;
+    execute!(io::stderr(), LeaveAlternateScreen).wrap_err("leaving alternate screen")?;
+    result
+}
+
+fn run(nodes: &mut [TreeNode], title: &str) -> eyre::Result<Option<String>> {
+    // draw on stderr so the selected node id can be captured from stdout
+    let mut terminal =
+        Terminal::new(CrosstermBackend::new(io::stderr())).wrap_err("creating terminal")?;
+    let title = format!(" {title} (enter: select, \u{2190}/\u{2192}: collapse/expand, q: quit) ");
+    let mut selected = 0;
+
+    loop {
+        let rows = visible_rows(nodes);
+        selected = selected.min(rows.len().saturating_sub(1));
+
+        let items: Vec<ListItem> = rows
+            .iter()
+            .map(|path| {
+                let node = node_at(nodes, path);
+                let marker = match (node.children.is_empty(), node.expanded) {
+                    (true, _) => ' ',
+                    (false, true) => '\u{25be}',
+                    (false, false) => '\u{25b8}',
+                };
+                let indent = "  ".repeat(path.len() - 1);
+                ListItem::new(format!("{indent}{marker} {}", node.label))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title.as_str()))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut list_state = ListState::default().with_selected(Some(selected));
+        terminal
+            .draw(|frame| frame.render_stateful_widget(list, frame.size(), &mut list_state))
+            .wrap_err("drawing tree")?;
+
+        let Event::Key(key) = event::read().wrap_err("reading terminal event")? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let Some(path) = rows.get(selected) else {
+            // nothing to select in an empty tree
+            if matches!(key.code, KeyCode::Esc | KeyCode::Char('q')) {
+                return Ok(None);
+            }
+            continue;
+        };
+
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+            KeyCode::Esc | KeyCode::Char('q') => return Ok(None),
+            KeyCode::Enter => return Ok(Some(node_at(nodes, path).node_id.clone())),
+            KeyCode::Up | KeyCode::Char('k') => selected = selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => selected += 1,
+            KeyCode::Home | KeyCode::Char('g') => selected = 0,
+            KeyCode::End | KeyCode::Char('G') => selected = rows.len() - 1,
+            KeyCode::Right | KeyCode::Char('l') => {
+                let node = node_at_mut(nodes, path);
+                if node.expanded {
+                    // step into the first child
+                    selected += 1;
+                } else {
+                    node.expanded = !node.children.is_empty();
+                }
+            }
+            KeyCode::Left | KeyCode::Char('h') => {
+                let node = node_at_mut(nodes, path);
+                if node.expanded {
+                    node.expanded = false;
+                } else if path.len() > 1 {
+                    // step out to the parent
+                    let parent = &path[..path.len() - 1];
+                    selected = rows
+                        .iter()
+                        .position(|row| row == parent)
+                        .unwrap_or(selected);
+                }
+            }
+            KeyCode::Char(' ') | KeyCode::Tab => {
+                let node = node_at_mut(nodes, path);
+                node.expanded = !node.expanded && !node.children.is_empty();
+            }
+            _ => {}
+        }
+    }
+}
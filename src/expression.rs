@@ -0,0 +1,196 @@
+//! Boolean expressions over names, as accepted by pytest's `-m` and `-k` options, e.g.
+//! `slow and not (network or db)`
+
+use std::{fmt, str::FromStr};
+
+/// A parsed expression, which selects everything if empty
+#[derive(Debug, Clone, Default)]
+pub struct Expression {
+    root: Option<Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Name(String),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+}
+
+impl Expression {
+    /// Evaluate the expression, where `matches` decides whether each name in it holds
+    pub fn evaluate(&self, matches: impl Fn(&str) -> bool) -> bool {
+        self.root
+            .as_ref()
+            .is_none_or(|root| root.evaluate(&matches))
+    }
+}
+
+impl Node {
+    fn evaluate(&self, matches: &impl Fn(&str) -> bool) -> bool {
+        match self {
+            Node::Name(name) => matches(name),
+            Node::Not(node) => !node.evaluate(matches),
+            Node::And(left, right) => left.evaluate(matches) && right.evaluate(matches),
+            Node::Or(left, right) => left.evaluate(matches) || right.evaluate(matches),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Name(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "'{name}'"),
+            Token::And => f.write_str("'and'"),
+            Token::Or => f.write_str("'or'"),
+            Token::Not => f.write_str("'not'"),
+            Token::Open => f.write_str("'('"),
+            Token::Close => f.write_str("')'"),
+        }
+    }
+}
+
+/// Characters allowed in a name, matching pytest
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | ':' | '+' | '-' | '.' | '[' | ']' | '\\' | '/')
+}
+
+/// Split `source` into tokens, each with its 1-based column
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let column = start + 1;
+        match c {
+            c if c.is_whitespace() => continue,
+            '(' => tokens.push((column, Token::Open)),
+            ')' => tokens.push((column, Token::Close)),
+            c if is_name_char(c) => {
+                let mut end = start + c.len_utf8();
+                while let Some((index, c)) = chars.next_if(|(_, c)| is_name_char(*c)) {
+                    end = index + c.len_utf8();
+                }
+                let token = match &source[start..end] {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    name => Token::Name(name.to_string()),
+                };
+                tokens.push((column, token));
+            }
+            c => return Err(format!("unexpected character '{c}' at column {column}")),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser for
+///
+/// ```text
+/// expression := or? EOF
+/// or         := and ('or' and)*
+/// and        := not ('and' not)*
+/// not        := 'not' not | '(' or ')' | name
+/// ```
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    /// Column just past the end of the source, for errors at the end of the input
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    fn accept(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error(&self, expected: &str) -> String {
+        match self.tokens.get(self.position) {
+            Some((column, token)) => {
+                format!("expected {expected} at column {column}, found {token}")
+            }
+            None => format!(
+                "expected {expected} at column {}, found end of input",
+                self.end
+            ),
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut node = self.and()?;
+        while self.accept(&Token::Or) {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut node = self.not()?;
+        while self.accept(&Token::And) {
+            node = Node::And(Box::new(node), Box::new(self.not()?));
+        }
+        Ok(node)
+    }
+
+    fn not(&mut self) -> Result<Node, String> {
+        if self.accept(&Token::Not) {
+            return Ok(Node::Not(Box::new(self.not()?)));
+        }
+        if self.accept(&Token::Open) {
+            let node = self.or()?;
+            if !self.accept(&Token::Close) {
+                return Err(self.error("')'"));
+            }
+            return Ok(node);
+        }
+        match self.peek() {
+            Some(Token::Name(name)) => {
+                let node = Node::Name(name.clone());
+                self.position += 1;
+                Ok(node)
+            }
+            _ => Err(self.error("a name, 'not' or '('")),
+        }
+    }
+}
+
+impl FromStr for Expression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            position: 0,
+            end: s.len() + 1,
+        };
+        let root = if parser.tokens.is_empty() {
+            None
+        } else {
+            let root = parser.or()?;
+            if parser.peek().is_some() {
+                return Err(parser.error("'and', 'or' or end of input"));
+            }
+            Some(root)
+        };
+        Ok(Self { root })
+    }
+}
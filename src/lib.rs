@@ -0,0 +1,19 @@
+//! Find the tests pytest would collect from Python source, without running Python
+//!
+//! This is the parser behind the `testsearch` binary, for callers which want the tests in a
+//! buffer they already hold:
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! let source = b"def test_add():\n    assert 1 + 1 == 2\n";
+//! let tests = testsearch_core::parse_source(source, Path::new("test_add.py"))?;
+//! assert_eq!(tests[0].to_string(), "test_add.py::test_add");
+//! # Ok::<(), color_eyre::eyre::Report>(())
+//! ```
+
+pub mod expression;
+mod parse;
+pub mod test_id;
+
+pub use parse::*;
@@ -1,12 +1,17 @@
 use std::{
     borrow::Cow,
-    collections::HashMap,
-    fmt, fs, io,
-    io::{BufRead, BufReader, Write},
+    collections::{BTreeSet, HashMap, HashSet},
+    fs, io,
+    io::{BufRead, BufReader, IsTerminal, Read, Write},
     path::{Path, PathBuf},
-    process::{ExitCode, Stdio},
+    process::{ExitCode, ExitStatus, Stdio},
     str::FromStr,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
     thread,
+    time::{Duration, Instant, SystemTime},
 };
 
 use clap::{CommandFactory, Parser, Subcommand};
@@ -15,19 +20,52 @@ use crossterm::{
     event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
     terminal::{disable_raw_mode, enable_raw_mode},
 };
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use ignore::WalkBuilder;
+use indicatif::{ProgressBar, ProgressStyle};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use regex::Regex;
 use rustyline::DefaultEditor;
 use serde::{Deserialize, Serialize};
 use skim::prelude::*;
 use tracing_subscriber::EnvFilter;
-use tree_sitter::Node;
 
-#[derive(Debug, Clone, Copy)]
+mod browse;
+mod update_check;
+
+use testsearch_core::{
+    Fixture, Framework, GrepFilter, GrepScope, ItemStyle, ParseOptions, TestCase,
+    UnsupportedEncoding, expression::Expression, has_syntax_error, parse_file_with_options,
+    parse_source, rootdir_relative, test_id::TestId,
+};
+use update_check::UpdateCheck;
+
+/// Whether `--quiet` was given, set once at startup
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Like `print!`, for banners and status messages which `--quiet` suppresses
+///
+/// Test output, selected node ids and errors are printed directly so they are always shown.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !is_quiet() {
+            print!($($arg)*);
+        }
+    };
+}
+
+#[derive(Debug, Clone)]
 enum CacheClearOption {
     Current,
     All,
+    /// The state of another directory, which need not exist any more
+    Path(PathBuf),
+    /// Tests in every directory which have not been run for this long
+    OlderThan(Duration),
 }
 
 impl FromStr for CacheClearOption {
@@ -45,13 +83,302 @@ impl FromStr for CacheClearOption {
 #[derive(Debug, clap::Args, Clone, Default)]
 #[command(version)]
 struct SearchArgs {
-    /// Paths to search for tests
+    /// Paths to search for tests, defaults to `$TESTSEARCH_ROOTS` (separated like `PATH`) or the
+    /// current directory
     #[arg(short, long)]
     root: Vec<PathBuf>,
 
     /// Print results rather than using fuzzy find
     #[arg(short, long)]
     no_fuzzy_selection: bool,
+
+    /// Select the first matching test, in sorted order, rather than using fuzzy find
+    ///
+    /// Intended for scripts, together with filters such as `--name` or `-k`.
+    #[arg(long, conflicts_with = "no_fuzzy_selection")]
+    first: bool,
+
+    /// Print how long each search phase took to stderr
+    #[arg(long)]
+    timing: bool,
+
+    /// Print a message to stderr when no test is selected
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Skip test files larger than this size, e.g. `500K` or `1M`, including those listed with
+    /// `--files-from`
+    #[arg(long, value_name = "BYTES", value_parser = parse_file_size)]
+    max_file_size: Option<u64>,
+
+    /// Show tests in a stable order (by file, class, name and parameter id) in the fuzzy finder
+    #[arg(long)]
+    sort: bool,
+
+    /// Do not list tests previously run in this directory first in the fuzzy finder
+    #[arg(long)]
+    no_history_boost: bool,
+
+    /// Warn about test files containing syntax errors
+    #[arg(long)]
+    warn_parse_errors: bool,
+
+    /// Fuzzy match against the full node id, including the file path, rather than just the
+    /// class and test name
+    #[arg(long)]
+    match_full: bool,
+
+    /// Also fuzzy match against each test's docstring, while still showing its name
+    #[arg(long)]
+    match_docstrings: bool,
+
+    /// Only show the first N tests, in sorted order
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Parse the files listed in FILE (one per line, `-` for stdin) instead of searching roots
+    #[arg(long, value_name = "FILE", conflicts_with = "root")]
+    files_from: Option<PathBuf>,
+
+    /// Also search the code cells of Jupyter notebooks (`*.ipynb`), for use with nbval
+    #[arg(long)]
+    notebooks: bool,
+
+    /// Only include tests whose name contains SUBSTRING (ignoring case)
+    #[arg(long, value_name = "SUBSTRING")]
+    name: Option<String>,
+
+    /// If a single test matches `--name`, select it without opening the fuzzy finder, like fzf's
+    /// `--select-1`
+    #[arg(long = "select-1", requires = "name")]
+    select_one: bool,
+
+    /// Only include tests whose pytest marks match EXPR, e.g. `slow and not network`, as with
+    /// pytest's `-m`
+    #[arg(short = 'm', long, value_name = "EXPR")]
+    markexpr: Option<Expression>,
+
+    /// Only include tests whose node ids match EXPR, e.g. `add or sub`, where each name matches
+    /// node ids containing it (ignoring case), as with pytest's `-k`
+    #[arg(short = 'k', long = "keyword", value_name = "EXPR")]
+    keyword: Option<Expression>,
+
+    /// How to print tests with `--no-fuzzy-selection`
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+
+    /// Show parametrized tests with more than N parameter combinations as a single test
+    /// [default: 100]
+    #[arg(long, value_name = "N")]
+    max_parametrize: Option<usize>,
+
+    /// Regular expression matched against class names to find test classes, like pytest's
+    /// `python_classes` [default: ^Test]
+    #[arg(long, value_name = "REGEX")]
+    class_pattern: Option<Regex>,
+
+    /// Regular expression matched against function names to find tests, like pytest's
+    /// `python_functions` [default: ^test_]
+    #[arg(long, value_name = "REGEX")]
+    function_pattern: Option<Regex>,
+
+    /// Also find tests defined inside `if`, `try`, `with` and `for` statements, which is slower
+    #[arg(long)]
+    deep: bool,
+
+    /// Glob matched against file names to find test files, instead of `test_*.py`, can be given
+    /// multiple times, defaults to `$TESTSEARCH_PATTERNS` (separated like `PATH`)
+    #[arg(long = "file-pattern", value_name = "GLOB")]
+    file_patterns: Vec<String>,
+
+    /// Do not skip `conftest.py`, `setup.py` and `__init__.py` when they match a file pattern
+    #[arg(long, requires = "file_patterns")]
+    no_default_excludes: bool,
+
+    /// Do not descend into directories called NAME, anywhere below the search roots, can be
+    /// given multiple times
+    #[arg(long = "exclude-dir", value_name = "NAME")]
+    exclude_dirs: Vec<String>,
+
+    /// Also descend into `.venv`, `__pycache__` and `.tox` directories
+    #[arg(long)]
+    no_default_exclude_dir: bool,
+
+    /// Only descend N directory levels below each search root, where 1 searches just the files
+    /// directly in the root
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Separate printed node ids with NUL bytes rather than newlines, for `xargs -0`
+    ///
+    /// This also terminates the node id of an interactively selected test.
+    /// It cannot be used with `--format json`, which is always one object per line.
+    #[arg(short = '0', long)]
+    null: bool,
+
+    /// Print node ids relative to the pytest rootdir (the closest directory above the search root
+    /// with a pytest config file or `setup.py`), as pytest reports them
+    #[arg(long)]
+    relative_to_rootdir: bool,
+
+    /// Also write the selected node id to FILE, replacing it atomically, for editor plugins;
+    /// nothing is written if no test is selected
+    #[arg(long, value_name = "FILE")]
+    selection_file: Option<PathBuf>,
+}
+
+/// Search roots used when `--root` is not given, separated like `PATH`
+const ROOTS_ENV_VAR: &str = "TESTSEARCH_ROOTS";
+/// File patterns used when `--file-pattern` is not given, separated like `PATH`
+const PATTERNS_ENV_VAR: &str = "TESTSEARCH_PATTERNS";
+
+impl SearchArgs {
+    /// Fill in the roots and file patterns from the environment, where no flag gave them
+    ///
+    /// Both variables are lists separated by `:`, or `;` on Windows, as for `PATH`. Flags take
+    /// precedence over the environment, which takes precedence over the built-in defaults.
+    fn with_env_defaults(mut self) -> Self {
+        if self.root.is_empty()
+            && self.files_from.is_none()
+            && let Some(roots) = std::env::var_os(ROOTS_ENV_VAR)
+        {
+            self.root = std::env::split_paths(&roots)
+                .filter(|root| !root.as_os_str().is_empty())
+                .collect();
+            tracing::debug!(roots = ?self.root, "using search roots from {ROOTS_ENV_VAR}");
+        }
+        if self.file_patterns.is_empty()
+            && let Some(patterns) = std::env::var_os(PATTERNS_ENV_VAR)
+        {
+            self.file_patterns = std::env::split_paths(&patterns)
+                .filter(|pattern| !pattern.as_os_str().is_empty())
+                .map(|pattern| pattern.to_string_lossy().into_owned())
+                .collect();
+            tracing::debug!(patterns = ?self.file_patterns, "using file patterns from {PATTERNS_ENV_VAR}");
+        }
+        self
+    }
+
+    /// How discovered files are parsed, with node ids relative to `rootdir` if given
+    ///
+    /// Every command that collects tests starts from these, so the filters behave the same
+    /// everywhere; callers only add what is specific to them, such as a grep filter.
+    fn parse_options<'a>(&'a self, rootdir: Option<&'a Path>) -> ParseOptions<'a> {
+        ParseOptions {
+            warn_parse_errors: self.warn_parse_errors,
+            style: ItemStyle {
+                match_full: self.match_full,
+                match_docstrings: self.match_docstrings,
+                dim_path: false,
+            },
+            rootdir,
+            name: self.name.as_deref().map(str::to_lowercase),
+            markexpr: self.markexpr.as_ref(),
+            keyword: self.keyword.as_ref(),
+            max_parametrize: self.max_parametrize,
+            class_pattern: self.class_pattern.as_ref(),
+            function_pattern: self.function_pattern.as_ref(),
+            deep: self.deep,
+            ..Default::default()
+        }
+    }
+}
+
+/// Parse a file size with an optional `K`, `M` or `G` (power of 1024) suffix
+fn parse_file_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (digits, multiplier) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1024),
+        Some((i, 'm' | 'M')) => (&s[..i], 1024 * 1024),
+        Some((i, 'g' | 'G')) => (&s[..i], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    let value: u64 = digits
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid file size: {s}"))?;
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("file size too large: {s}"))
+}
+
+#[derive(Debug, clap::Args, Clone)]
+struct GrepArgs {
+    /// Regular expression pattern to search for in test function bodies
+    #[arg(required_unless_present = "patterns")]
+    pattern: Option<String>,
+
+    /// Additional pattern to search for, can be given multiple times
+    #[arg(short = 'e', long = "pattern", value_name = "PATTERN")]
+    patterns: Vec<String>,
+
+    /// Select tests matching all of the patterns
+    #[arg(long, conflicts_with = "any")]
+    all: bool,
+
+    /// Select tests matching any of the patterns (the default)
+    #[arg(long)]
+    any: bool,
+
+    /// Command template to execute matching tests (use {} as placeholder for test path), inferred
+    /// from the project if no template is given
+    #[arg(long, value_name = "COMMAND", num_args = 0..=1)]
+    run: Option<Option<String>>,
+
+    /// Run the command inside the project environment managed by this tool
+    #[arg(long, value_enum, default_value_t)]
+    runner: Runner,
+
+    /// Run the command over ssh on another host, in the directory matching this one, e.g.
+    /// `user@host:/srv/project`
+    ///
+    /// Absolute node ids below this directory are rewritten onto the remote path. The local
+    /// environment, such as `PYTEST_ADDOPTS`, is not forwarded.
+    #[arg(long, value_name = "[USER@]HOST:PATH", requires = "run")]
+    remote: Option<Remote>,
+
+    /// Extra options to append to the command template, e.g. `--addopts "-x --lf"`
+    #[arg(
+        long,
+        value_name = "OPTIONS",
+        requires = "run",
+        allow_hyphen_values = true
+    )]
+    addopts: Option<String>,
+
+    /// Stop running tests after the first one fails
+    #[arg(long, requires = "run")]
+    fail_fast: bool,
+
+    /// Also write the output of each test to `DIR/<node id>.log`, with characters which are
+    /// unsafe in file names percent-encoded
+    #[arg(long, value_name = "DIR", requires = "run")]
+    output_dir: Option<PathBuf>,
+
+    /// Write a JUnit XML report with the outcome and duration of each test to FILE
+    #[arg(long, value_name = "FILE", requires = "run")]
+    junit: Option<PathBuf>,
+
+    /// Run the matching tests in a random order, reproducible by passing the printed SEED
+    #[arg(long, value_name = "SEED", num_args = 0..=1, require_equals = true, requires = "run")]
+    shuffle: Option<Option<u64>>,
+
+    /// Select tests whose body does not match the pattern
+    ///
+    /// Unlike grep there is no `-v`, which is `--verbose`.
+    #[arg(long)]
+    invert_match: bool,
+
+    /// Also print the line number and text of each match
+    #[arg(long, conflicts_with = "null")]
+    show_match: bool,
+
+    /// Match against each test body, or the whole file the test is defined in
+    #[arg(long, value_enum, default_value_t)]
+    scope: GrepScope,
+
+    #[command(flatten)]
+    search_args: SearchArgs,
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -59,6 +386,9 @@ enum Command {
     // Search for a test or rerun the last test
     Search(SearchArgs),
     /// Rerun a previous test
+    ///
+    /// Press ctrl-g in the fuzzy finder to switch between this directory's history and the
+    /// history of every directory.
     Rerun {
         /// Path to re-run tests from
         root: Option<PathBuf>,
@@ -66,25 +396,129 @@ enum Command {
         /// Automatically pick the most recent test
         #[arg(short, long)]
         last: bool,
+
+        /// Pick from the history of every directory
+        #[arg(short, long, conflicts_with_all = ["root", "last"])]
+        global: bool,
+
+        /// Pick from the tests pinned with `testsearch pin` instead of the history
+        #[arg(short, long, conflicts_with = "global")]
+        pinned: bool,
+    },
+    /// Pin a test in the current directory, so `rerun --pinned` can pick it
+    Pin {
+        /// Node id of the test to pin
+        node_id: String,
+    },
+    /// Unpin a test pinned in the current directory
+    Unpin {
+        /// Node id of the test to unpin
+        node_id: String,
     },
     /// Start interactive REPL mode
     Repl {
-        /// Command template to execute tests (use {} as placeholder for test path)
+        /// Command template to execute tests (use {} as placeholder for test path), inferred
+        /// from the project if not given
         #[arg(value_name = "COMMAND")]
-        command: String,
+        command: Option<String>,
+
+        /// Run the command inside the project environment managed by this tool
+        #[arg(long, value_enum, default_value_t)]
+        runner: Runner,
+
+        /// Run the command over ssh on another host, in the directory matching this one, e.g.
+        /// `user@host:/srv/project`
+        ///
+        /// Absolute node ids below this directory are rewritten onto the remote path. The local
+        /// environment, such as `PYTEST_ADDOPTS`, is not forwarded.
+        #[arg(long, value_name = "[USER@]HOST:PATH")]
+        remote: Option<Remote>,
+
+        /// Extra options to append to the command template, e.g. `--addopts "-x --lf"`
+        #[arg(long, value_name = "OPTIONS", allow_hyphen_values = true)]
+        addopts: Option<String>,
+
+        /// Show the command which will run each test, and ask for confirmation before running it
+        #[arg(long)]
+        confirm: bool,
     },
+    /// Browse tests as a collapsible tree of files, classes and tests, printing the node id of
+    /// the selected one
+    Browse(SearchArgs),
     /// Search for tests containing specific function calls
-    Grep {
-        /// Regular expression pattern to search for in test function bodies
-        pattern: String,
+    Grep(GrepArgs),
+    /// Write the node ids of all discovered tests, sorted, one per line
+    Collect {
+        /// File to write the node ids to, defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
 
-        /// Command template to execute matching tests (use {} as placeholder for test path)
-        #[arg(long)]
-        run: Option<String>,
+        /// Only write the tests in shard `i` of `n` (1-based), e.g. `2/5`
+        #[arg(long, value_name = "i/n")]
+        shard: Option<Shard>,
 
         #[command(flatten)]
         search_args: SearchArgs,
     },
+    /// Show which tests were added or removed between two collections, e.g. before and after a
+    /// refactor
+    ///
+    /// Each side is either a directory, whose tests are collected with node ids relative to the
+    /// pytest rootdir, or a file of node ids such as one written by
+    /// `collect --relative-to-rootdir --output FILE`. Exits with 1 if any test was removed.
+    Diff {
+        /// The collection before the change
+        old: PathBuf,
+        /// The collection after the change
+        new: PathBuf,
+    },
+    /// Run the tests listed in a file of node ids, such as one written by `collect --output`
+    ///
+    /// Each test is run by its own command. Afterwards the number of tests which passed, failed
+    /// or could not be run is printed, and the exit code is 1 if any did not pass.
+    Run {
+        /// File to read node ids from, one per line, or `-` for stdin
+        #[arg(long, value_name = "FILE")]
+        from: PathBuf,
+
+        /// Command template to execute each test (use {} as placeholder for test path), inferred
+        /// from the project if not given
+        #[arg(long, value_name = "COMMAND")]
+        command: Option<String>,
+
+        /// Run the command inside the project environment managed by this tool
+        #[arg(long, value_enum, default_value_t)]
+        runner: Runner,
+
+        /// Run the command over ssh on another host, in the directory matching this one, e.g.
+        /// `user@host:/srv/project`
+        ///
+        /// Absolute node ids below this directory are rewritten onto the remote path. The local
+        /// environment, such as `PYTEST_ADDOPTS`, is not forwarded.
+        #[arg(long, value_name = "[USER@]HOST:PATH")]
+        remote: Option<Remote>,
+
+        /// Extra options to append to the command template, e.g. `--addopts "-x --lf"`
+        #[arg(long, value_name = "OPTIONS", allow_hyphen_values = true)]
+        addopts: Option<String>,
+
+        /// Stop running tests after the first one fails
+        #[arg(long)]
+        fail_fast: bool,
+
+        /// Also write the output of each test to `DIR/<node id>.log`, with characters which are
+        /// unsafe in file names percent-encoded
+        #[arg(long, value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+
+        /// Write a JUnit XML report with the outcome and duration of each test to FILE
+        #[arg(long, value_name = "FILE")]
+        junit: Option<PathBuf>,
+
+        /// Run the tests in a random order, reproducible by passing the printed SEED
+        #[arg(long, value_name = "SEED", num_args = 0..=1, require_equals = true)]
+        shuffle: Option<Option<u64>>,
+    },
     /// View or manage state
     State {
         #[command(subcommand)]
@@ -96,6 +530,248 @@ enum Command {
         #[arg(value_enum)]
         shell: clap_complete::Shell,
     },
+    /// Find pytest fixtures defined in `conftest.py` and test files, printing the location of the
+    /// selected fixture
+    Fixtures(SearchArgs),
+    /// Print the node id of the test enclosing a position, e.g. `tests/test_foo.py:42`
+    ///
+    /// Intended for "run the test under the cursor" editor bindings.
+    Locate {
+        /// File and 1-based line number to look up
+        #[arg(value_name = "FILE:LINE")]
+        position: Position,
+    },
+    /// Parse Python source from stdin and print the node ids of the tests it defines
+    ///
+    /// Intended for editors, to list the tests in an unsaved buffer without a temporary file.
+    ParseStdin {
+        /// Path the source is treated as coming from, used in node ids
+        #[arg(long, default_value = "test_stdin.py")]
+        filename: PathBuf,
+    },
+    /// Print version information
+    Version {
+        /// Also print the versions of the parser and fuzzy finder, for bug reports
+        #[arg(long)]
+        verbose: bool,
+    },
+    /// Print a shell integration snippet, e.g. `eval "$(testsearch init zsh)"`
+    ///
+    /// The snippet binds alt-t to pick a test and insert `pytest <test>` on the command line.
+    Init {
+        /// The shell to generate the integration for
+        #[arg(value_enum)]
+        shell: InitShell,
+    },
+}
+
+impl Command {
+    /// Fill in the search arguments of searching commands from the environment, see
+    /// [`SearchArgs::with_env_defaults`]
+    fn with_env_defaults(self) -> Self {
+        match self {
+            Command::Search(args) => Command::Search(args.with_env_defaults()),
+            Command::Browse(args) => Command::Browse(args.with_env_defaults()),
+            Command::Fixtures(args) => Command::Fixtures(args.with_env_defaults()),
+            Command::Grep(mut args) => {
+                args.search_args = args.search_args.with_env_defaults();
+                Command::Grep(args)
+            }
+            Command::Collect {
+                output,
+                shard,
+                search_args,
+            } => Command::Collect {
+                output,
+                shard,
+                search_args: search_args.with_env_defaults(),
+            },
+            other => other,
+        }
+    }
+}
+
+/// Tool used to run test commands inside a managed environment
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+enum Runner {
+    Uv,
+    Poetry,
+    Hatch,
+    /// Run the command as given
+    #[default]
+    None,
+}
+
+impl Runner {
+    /// The command prefix which runs a command inside the environment
+    fn prefix(self) -> Option<&'static str> {
+        match self {
+            Runner::Uv => Some("uv run"),
+            Runner::Poetry => Some("poetry run"),
+            Runner::Hatch => Some("hatch run"),
+            Runner::None => None,
+        }
+    }
+
+    /// Prefix `command_template` so it runs inside the environment
+    fn wrap(self, command_template: String) -> String {
+        let Some(prefix) = self.prefix() else {
+            return command_template;
+        };
+
+        // the inferred template may already use the runner
+        if command_template.starts_with(prefix) {
+            return command_template;
+        }
+        format!("{prefix} {command_template}")
+    }
+
+    /// Warn if the runner is not installed locally
+    fn warn_if_missing(self) {
+        let Some(prefix) = self.prefix() else {
+            return;
+        };
+        let program = prefix.split_whitespace().next().unwrap_or(prefix);
+        if !is_executable_on_path(program) {
+            eprintln!("warning: runner `{program}` was not found on PATH");
+        }
+    }
+}
+
+/// A host to run test commands on over ssh, given on the command line as `[user@]host:path`
+#[derive(Debug, Clone)]
+struct Remote {
+    /// Where ssh connects to, e.g. `user@host`
+    destination: String,
+    /// The directory on the remote host which corresponds to the local working directory
+    path: String,
+}
+
+impl FromStr for Remote {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (destination, path) = s
+            .split_once(':')
+            .ok_or_else(|| format!("invalid remote {s}, expected [user@]host:path"))?;
+        if destination.is_empty() || path.is_empty() {
+            return Err(format!("invalid remote {s}, expected [user@]host:path"));
+        }
+        // commands are split on whitespace before they are run
+        if s.contains(char::is_whitespace) {
+            return Err("remote hosts and paths cannot contain whitespace".to_string());
+        }
+        Ok(Self {
+            destination: destination.to_string(),
+            path: path.to_string(),
+        })
+    }
+}
+
+impl Remote {
+    /// Wrap `command_template` in an ssh invocation which runs it in the remote directory
+    ///
+    /// ssh joins its arguments into a single command for the remote shell, so the node ids put
+    /// in place of `{}` must be quoted with [`shell_quote`] when the template is expanded.
+    fn wrap(&self, command_template: &str) -> String {
+        format!(
+            "ssh {} -- cd {} && {}",
+            self.destination, self.path, command_template
+        )
+    }
+
+    /// Translate `node_id` for the remote host, replacing a local path below the working
+    /// directory with the matching remote path
+    ///
+    /// Relative node ids are left as they are, since the command runs in the remote directory.
+    fn translate<'a>(&self, node_id: &'a TestId) -> Cow<'a, TestId> {
+        if !node_id.file.is_absolute() {
+            return Cow::Borrowed(node_id);
+        }
+        let relative = current_dir()
+            .ok()
+            .and_then(|here| Some(node_id.file.strip_prefix(here).ok()?.to_path_buf()));
+        match relative {
+            Some(relative) => Cow::Owned(TestId {
+                file: Path::new(&self.path).join(relative),
+                ..node_id.clone()
+            }),
+            None => {
+                eprintln!(
+                    "warning: {node_id} is outside the working directory, running it with its \
+                     local path"
+                );
+                Cow::Borrowed(node_id)
+            }
+        }
+    }
+}
+
+/// Quote `s` as a single word for a POSIX shell
+///
+/// Node ids run over ssh go through the remote shell, which would otherwise glob the `[...]` of
+/// parametrized tests and act on any quotes in their parameter ids.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// The node id to give the test command, translated for the remote host if there is one
+fn remote_test_id<'a>(node_id: &'a TestId, remote: Option<&Remote>) -> Cow<'a, TestId> {
+    match remote {
+        Some(remote) => remote.translate(node_id),
+        None => Cow::Borrowed(node_id),
+    }
+}
+
+/// The full command template to run tests with: `command_template` inside the runner's
+/// environment, followed by any `--addopts`, and run over ssh with `--remote`
+fn build_command_template(
+    command_template: String,
+    runner: Runner,
+    addopts: Option<&str>,
+    remote: Option<&Remote>,
+) -> String {
+    let command_template = append_addopts(runner.wrap(command_template), addopts);
+    match remote {
+        Some(remote) => remote.wrap(&command_template),
+        None => {
+            // the runner only has to be installed where the command runs
+            runner.warn_if_missing();
+            command_template
+        }
+    }
+}
+
+/// Append the options given with `--addopts` to `command_template`
+///
+/// The options go at the end, after the `{}` placeholder, which suits pytest and unittest alike.
+fn append_addopts(command_template: String, addopts: Option<&str>) -> String {
+    match addopts.map(str::trim) {
+        Some(addopts) if !addopts.is_empty() => format!("{command_template} {addopts}"),
+        _ => command_template,
+    }
+}
+
+/// Check whether `program` is an executable file in one of the `PATH` directories
+fn is_executable_on_path(program: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+    std::env::split_paths(&path).any(|dir| {
+        let candidate = dir.join(program);
+        if cfg!(windows) {
+            candidate.with_extension("exe").is_file()
+        } else {
+            candidate.is_file()
+        }
+    })
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum InitShell {
+    Bash,
+    Zsh,
+    Fish,
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -105,17 +781,137 @@ enum StateCommand {
         /// Clear the state for all directories
         #[arg(short, long)]
         all: bool,
+
+        /// Clear the state for DIR rather than the current directory, e.g. a project which has
+        /// since been moved or deleted
+        #[arg(long, value_name = "DIR", conflicts_with = "all")]
+        path: Option<PathBuf>,
+
+        /// Instead remove the tests in every directory which have not been run within DURATION,
+        /// e.g. `30d` or `2weeks`
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = humantime::parse_duration,
+            conflicts_with_all = ["all", "path"]
+        )]
+        older_than: Option<Duration>,
     },
     /// Show the state contents
     Show {
         /// Show the last run test for every directory
         #[arg(short, long)]
         all: bool,
+
+        /// Print the state as pretty JSON, the default
+        #[arg(long, conflicts_with = "plain")]
+        json: bool,
+
+        /// Print the node ids run in this directory, one per line, for use in scripts
+        #[arg(long, conflicts_with = "all")]
+        plain: bool,
+
+        /// List each test run in this directory as an object with its `node_id` and
+        /// `last_duration` in seconds, rather than just its node id
+        #[arg(long, conflicts_with_all = ["all", "plain"])]
+        durations: bool,
+    },
+    /// Remove history entries for tests that no longer exist
+    ///
+    /// An entry is only removed if its file is gone, or the file parses cleanly and no longer
+    /// defines a function of that name, whichever patterns or flags found it. Entries for whole
+    /// files, classes and notebooks are kept while their file exists.
+    Prune {
+        /// Only print the entries that would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Also remove the history of directories that no longer exist
+        #[arg(short, long)]
+        missing_dirs: bool,
     },
 }
 
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum ColorMode {
+    /// Use colours unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => std::env::var_os("NO_COLOR").is_none(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Human,
+    /// One JSON object per line, for log pipelines
+    Json,
+}
+
 #[derive(Debug, Parser)]
+#[command(after_help = "Fuzzy finder keybindings:
+  enter   Select the highlighted test
+  ctrl-e  Open the highlighted test in $VISUAL or $EDITOR")]
 struct Args {
+    /// Run as if testsearch was started in DIR, which also selects the history used
+    #[arg(short = 'C', long, global = true, value_name = "DIR")]
+    chdir: Option<PathBuf>,
+
+    /// Do not show progress, banners or status messages, only test output and errors
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Check crates.io for a newer version at most once a day, in the background, and print a
+    /// hint to stderr if there is one (unless `--quiet`)
+    #[arg(
+        long,
+        global = true,
+        env = "TESTSEARCH_CHECK_UPDATES",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    check_updates: bool,
+
+    /// Read the history but do not record anything, never writing to the cache directory
+    #[arg(
+        long,
+        global = true,
+        env = "TESTSEARCH_NO_HISTORY",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    no_history: bool,
+
+    /// Format of the log messages written to stderr, filtered by `RUST_LOG`
+    #[arg(long, global = true, value_enum, default_value_t)]
+    log_format: LogFormat,
+
+    /// Directory to store the persistent state in (defaults to the system cache dir)
+    #[arg(long, global = true, env = "TESTSEARCH_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// When to use colours in the fuzzy finder
+    #[arg(long, global = true, value_enum, default_value_t)]
+    color: ColorMode,
+
+    /// Prompt shown in the fuzzy finder
+    #[arg(long, global = true, default_value = "test> ")]
+    prompt: String,
+
+    /// Header shown in the fuzzy finder, instead of the number of items and where they were found
+    #[arg(long, global = true)]
+    header: Option<String>,
+
     #[command(flatten)]
     search: Option<SearchArgs>,
 
@@ -127,9 +923,19 @@ fn current_dir() -> eyre::Result<PathBuf> {
     std::env::current_dir().wrap_err("locating current directory")
 }
 
-#[derive(Serialize, Deserialize, Default)]
+/// Version of the cache format written by this version of testsearch
+///
+/// Caches written before the format was versioned have no `version` field and are read as
+/// version 0.
+const CACHE_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
 struct PersistedState {
-    /// Persisted history of all previous test runs
+    /// Version of the cache format, see [`CACHE_VERSION`]
+    #[serde(default)]
+    version: u32,
+
+    /// Persisted history of all previous test runs, oldest first
     #[serde(default)]
     test_history: Option<HashMap<PathBuf, Vec<String>>>,
 
@@ -138,344 +944,2626 @@ struct PersistedState {
     /// The HashMap is a mapping from directory to test name
     ///
     /// legacy option
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     last_test: Option<HashMap<PathBuf, String>>,
+
+    /// Tests pinned with `testsearch pin`, per directory, in the order they were pinned
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pinned: HashMap<PathBuf, Vec<String>>,
+
+    /// How long each test took, in seconds, the last time testsearch ran it, per directory
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    durations: HashMap<PathBuf, HashMap<String, f64>>,
+
+    /// When each test in the history or durations was last run, in seconds since the Unix
+    /// epoch, per directory
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    last_run: HashMap<PathBuf, HashMap<String, u64>>,
+
+    /// When `--check-updates` last asked crates.io for the latest version, and the answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    update_check: Option<UpdateCheck>,
+
+    /// The system colour scheme, so it is not detected on every invocation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    colour_scheme: Option<DetectedColourScheme>,
+}
+
+impl Default for PersistedState {
+    fn default() -> Self {
+        Self {
+            version: CACHE_VERSION,
+            test_history: None,
+            last_test: None,
+            pinned: HashMap::new(),
+            durations: HashMap::new(),
+            last_run: HashMap::new(),
+            update_check: None,
+            colour_scheme: None,
+        }
+    }
 }
 
 impl PersistedState {
+    /// The tests previously run in `path`, oldest first
+    ///
+    /// Migration removes the legacy `last_test` field, but a hand-edited or partially migrated
+    /// cache may still have it, possibly without `test_history`. Its entry is then treated as the
+    /// most recent test rather than being ignored.
     fn history(&self, path: impl AsRef<Path>) -> Option<Vec<String>> {
         let path = path.as_ref();
-        match (self.test_history.as_ref(), self.last_test.as_ref()) {
-            (Some(h), _) => h.get(path).cloned(),
-            (None, Some(_)) => panic!("we should never have last_test but not test_history"),
-            _ => None,
+        let mut history = self
+            .test_history
+            .as_ref()
+            .and_then(|test_history| test_history.get(path).cloned());
+
+        if let Some(last_test) = self.last_test.as_ref().and_then(|last| last.get(path)) {
+            // `migrate_settings` merges `last_test` into the history on load, so this only
+            // happens for a state which was never migrated, and is not worth a warning per call
+            tracing::debug!("cache has unmigrated last_test entries, reading from last_test");
+            let history = history.get_or_insert_with(Vec::new);
+            if history.last() != Some(last_test) {
+                history.retain(|test| test != last_test);
+                history.push(last_test.clone());
+            }
+        }
+
+        history
+    }
+
+    /// Record `test` as the most recent test run in `path`
+    fn push_history(&mut self, path: PathBuf, test: String) {
+        self.mark_run(path.clone(), test.clone());
+        let history = self
+            .test_history
+            .get_or_insert_with(HashMap::new)
+            .entry(path)
+            .or_default();
+        // keep a single entry per test, ordered by when it was last run
+        history.retain(|existing| existing != &test);
+        history.push(test);
+    }
+
+    /// Record that `test` was run in `path` just now
+    fn mark_run(&mut self, path: PathBuf, test: String) {
+        self.last_run
+            .entry(path)
+            .or_default()
+            .insert(test, unix_time_now());
+    }
+
+    /// Remove the tests in `path` for which `remove` returns true from the history, durations
+    /// and run times, dropping the directory once nothing is left
+    fn remove_tests(&mut self, path: &Path, remove: impl Fn(&str) -> bool) {
+        if let Some(history) = self.test_history.as_mut()
+            && let Some(tests) = history.get_mut(path)
+        {
+            tests.retain(|test| !remove(test));
+            if tests.is_empty() {
+                history.remove(path);
+            }
+        }
+        if let Some(durations) = self.durations.get_mut(path) {
+            durations.retain(|test, _| !remove(test));
+            if durations.is_empty() {
+                self.durations.remove(path);
+            }
+        }
+        if let Some(last_run) = self.last_run.get_mut(path) {
+            last_run.retain(|test, _| !remove(test));
+            if last_run.is_empty() {
+                self.last_run.remove(path);
+            }
+        }
+    }
+
+    /// Remove the tests in every directory which have not been run within `max_age`, returning
+    /// how many were removed
+    ///
+    /// Tests without a recorded run time are kept, since their age is unknown.
+    fn remove_older_than(&mut self, max_age: Duration) -> usize {
+        let cutoff = unix_time_now().saturating_sub(max_age.as_secs());
+        let stale: Vec<(PathBuf, HashSet<String>)> = self
+            .last_run
+            .iter()
+            .map(|(path, tests)| {
+                let stale = tests
+                    .iter()
+                    .filter(|&(_, &run_at)| run_at < cutoff)
+                    .map(|(test, _)| test.clone())
+                    .collect();
+                (path.clone(), stale)
+            })
+            .collect();
+
+        let mut removed = 0;
+        for (path, tests) in stale {
+            removed += tests.len();
+            self.remove_tests(&path, |test| tests.contains(test));
+        }
+        removed
+    }
+
+    /// The tests recorded in `path`: its history, oldest first, then the tests which only have
+    /// a duration, or `None` if nothing was ever recorded there
+    fn recorded_tests(&self, path: &Path) -> Option<Vec<String>> {
+        let history = self.history(path);
+        // tests run in batches by `run` or `grep --run` have a duration but no history entry, so
+        // list them after the history
+        let mut timed_only: Vec<String> = self
+            .durations
+            .get(path)
+            .into_iter()
+            .flat_map(|durations| durations.keys())
+            .filter(|test| history.as_ref().is_none_or(|tests| !tests.contains(test)))
+            .cloned()
+            .collect();
+        timed_only.sort();
+
+        if history.is_none() && timed_only.is_empty() {
+            return None;
         }
+        Some(history.into_iter().flatten().chain(timed_only).collect())
+    }
+
+    /// How long `test` took the last time it was run in `path`
+    fn last_duration(&self, path: &Path, test: &str) -> Option<Duration> {
+        let seconds = self.durations.get(path)?.get(test)?;
+        Duration::try_from_secs_f64(*seconds).ok()
+    }
+
+    /// Pin `test` in `path`, returning whether it was not already pinned
+    fn pin(&mut self, path: PathBuf, test: String) -> bool {
+        let pinned = self.pinned.entry(path).or_default();
+        if pinned.contains(&test) {
+            return false;
+        }
+        pinned.push(test);
+        true
+    }
+
+    /// Unpin `test` in `path`, returning whether it was pinned
+    fn unpin(&mut self, path: &Path, test: &str) -> bool {
+        let Some(pinned) = self.pinned.get_mut(path) else {
+            return false;
+        };
+        let count = pinned.len();
+        pinned.retain(|existing| existing != test);
+        let removed = pinned.len() != count;
+        if pinned.is_empty() {
+            self.pinned.remove(path);
+        }
+        removed
     }
 
     fn clear(&mut self, clear_option: CacheClearOption) -> eyre::Result<()> {
         match clear_option {
             CacheClearOption::Current => {
                 let here = current_dir()?;
-                if let Some(last_test) = self.last_test.as_mut() {
-                    last_test.remove(&here);
-                }
-                if let Some(history) = self.test_history.as_mut() {
-                    history.remove(&here);
-                }
+                self.clear_dir(&here);
             }
             CacheClearOption::All => {
                 *self = Self::default();
             }
+            CacheClearOption::Path(path) => {
+                // directories are stored as the canonical working directory, but a project which
+                // has been deleted can only be made absolute
+                let dir = std::fs::canonicalize(&path)
+                    .or_else(|_| std::path::absolute(&path))
+                    .wrap_err_with(|| format!("resolving {}", path.display()))?;
+                if !self.clear_dir(&dir) {
+                    eprintln!("warning: no state is stored for {}", dir.display());
+                }
+            }
+            CacheClearOption::OlderThan(max_age) => {
+                let removed = self.remove_older_than(max_age);
+                status!(
+                    "Removed {removed} test(s) not run in the last {}\n",
+                    humantime::format_duration(max_age)
+                );
+            }
         }
         Ok(())
     }
 
+    /// Remove everything stored for `dir`, returning whether there was anything
+    fn clear_dir(&mut self, dir: &Path) -> bool {
+        let mut removed = self.pinned.remove(dir).is_some();
+        removed |= self.durations.remove(dir).is_some();
+        removed |= self.last_run.remove(dir).is_some();
+        if let Some(last_test) = self.last_test.as_mut() {
+            removed |= last_test.remove(dir).is_some();
+        }
+        if let Some(history) = self.test_history.as_mut() {
+            removed |= history.remove(dir).is_some();
+        }
+        removed
+    }
+
+    /// Upgrade the state to the current cache format, one version at a time
     fn migrate_settings(&mut self) -> eyre::Result<()> {
-        if let Some(last_test) = self.last_test.take() {
-            let mut test_history = HashMap::new();
-            for (path, test) in last_test {
-                test_history.insert(path, vec![test]);
+        if self.version > CACHE_VERSION {
+            tracing::warn!(
+                version = self.version,
+                supported = CACHE_VERSION,
+                "cache was written by a newer version of testsearch, reading it on a best-effort basis"
+            );
+            self.version = CACHE_VERSION;
+        }
+
+        while self.version < CACHE_VERSION {
+            tracing::debug!(from = self.version, "migrating cache");
+            match self.version {
+                // unversioned caches recorded the last test per directory in `last_test`
+                0 => self.merge_last_test(),
+                // run times were not recorded, so count every known test as run now, rather than
+                // letting `state clear --older-than` remove them all at once
+                1 => self.mark_all_run(),
+                version => eyre::bail!("no migration from cache version {version}"),
             }
-            self.test_history = Some(test_history);
+            self.version += 1;
+        }
+
+        // current caches never contain legacy fields, but recover them if a cache was edited
+        if self.last_test.is_some() {
+            tracing::warn!("cache has legacy last_test entries, merging them into test_history");
+            self.merge_last_test();
         }
 
         Ok(())
     }
+
+    /// Record every test in the history and durations as run just now
+    fn mark_all_run(&mut self) {
+        let now = unix_time_now();
+        let history = self
+            .test_history
+            .iter()
+            .flatten()
+            .flat_map(|(path, tests)| tests.iter().map(move |test| (path, test)));
+        let durations = self
+            .durations
+            .iter()
+            .flat_map(|(path, durations)| durations.keys().map(move |test| (path, test)));
+        for (path, test) in history.chain(durations) {
+            self.last_run
+                .entry(path.clone())
+                .or_default()
+                .insert(test.clone(), now);
+        }
+    }
+
+    /// Move the legacy `last_test` entries onto the end of each directory's history
+    fn merge_last_test(&mut self) {
+        for (path, test) in self.last_test.take().unwrap_or_default() {
+            self.push_history(path, test);
+        }
+    }
+}
+
+/// What `state show` prints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShowMode {
+    /// The whole cache, as pretty JSON
+    All,
+    /// The node ids of the tests recorded in the current directory, as a pretty JSON list
+    Tests,
+    /// The tests recorded in the current directory with their last durations, as pretty JSON
+    Durations,
+    /// The node ids of the tests recorded in the current directory, one per line
+    Plain,
+}
+
+/// A test in the history of a directory, as shown by `state show --durations`
+#[derive(Serialize)]
+struct HistoryEntry<'a> {
+    node_id: &'a str,
+    /// Seconds the test took the last time it was run, if known
+    last_duration: Option<f64>,
+}
+
+/// The current time in seconds since the Unix epoch, as stored in the cache
+fn unix_time_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
 }
 
 struct State {
     persisted: PersistedState,
     cache_file: PathBuf,
+    lock_file: PathBuf,
+    /// Read the cache but never write to the cache directory, for `--no-history`
+    read_only: bool,
 }
 
 impl State {
-    fn new(cache_root: impl AsRef<Path>) -> eyre::Result<Self> {
+    fn new(cache_root: impl AsRef<Path>, read_only: bool) -> eyre::Result<Self> {
         let cache_root = cache_root.as_ref();
-        std::fs::create_dir_all(cache_root).wrap_err("creating cache dir")?;
         let cache_file = cache_root.join("cache.json");
+        let lock_file = cache_root.join("cache.lock");
+        let mut state = Self {
+            persisted: PersistedState::default(),
+            cache_file,
+            lock_file,
+            read_only,
+        };
+
+        // the cache is replaced atomically, so it can be read without the lock
+        if read_only {
+            state.reload()?;
+            return Ok(state);
+        }
+
+        std::fs::create_dir_all(cache_root)
+            .wrap_err_with(|| format!("creating cache dir {}", cache_root.display()))?;
+        let _lock = state.lock()?;
+        state.reload()?;
+        Ok(state)
+    }
+
+    /// Take an exclusive advisory lock on the cache, released when the returned file is dropped
+    ///
+    /// This blocks until any other `testsearch` process has finished its read-modify-write of
+    /// the cache. The lock is only held for the duration of a single update, so waiting is
+    /// brief, and persisting is never skipped.
+    fn lock(&self) -> eyre::Result<std::fs::File> {
+        let lock_file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.lock_file)
+            .wrap_err("opening cache lock file")?;
+        lock_file.lock().wrap_err("locking cache")?;
+        Ok(lock_file)
+    }
 
-        let persisted_state = if cache_file.is_file() {
-            let mut f = std::fs::File::open(&cache_file).wrap_err("opening existing cache file")?;
-            serde_json::from_reader(&mut f).wrap_err("decoding existing cache file")?
+    /// Re-read the persisted state from disk. Must be called with the cache lock held.
+    fn reload(&mut self) -> eyre::Result<()> {
+        self.persisted = if self.cache_file.is_file() {
+            let f =
+                std::fs::File::open(&self.cache_file).wrap_err("opening existing cache file")?;
+            match serde_json::from_reader(BufReader::new(f)) {
+                Ok(persisted_state) => persisted_state,
+                Err(e) => {
+                    // keep the undecodable cache around for inspection, but do not let it
+                    // prevent the tool from running
+                    let backup_file = self.cache_file.with_extension("json.bak");
+                    if self.read_only {
+                        tracing::warn!(error = %e, "could not decode cache file, ignoring it");
+                        self.persisted = PersistedState::default();
+                        return Ok(());
+                    }
+                    tracing::warn!(
+                        error = %e,
+                        backup = %backup_file.display(),
+                        "could not decode cache file, starting with an empty cache"
+                    );
+                    std::fs::rename(&self.cache_file, &backup_file)
+                        .wrap_err("backing up undecodable cache file")?;
+                    PersistedState::default()
+                }
+            }
         } else {
             PersistedState::default()
         };
+        Ok(())
+    }
 
-        Ok(Self {
-            persisted: persisted_state,
-            cache_file,
-        })
+    /// Apply a change to the persisted state and write it back to disk
+    ///
+    /// The latest state is re-read under the cache lock before applying the change, so
+    /// concurrent invocations do not clobber each other's history. A read-only state only
+    /// applies the change in memory.
+    fn update(
+        &mut self,
+        f: impl FnOnce(&mut PersistedState) -> eyre::Result<()>,
+    ) -> eyre::Result<()> {
+        if self.read_only {
+            tracing::debug!("not writing cache changes with --no-history");
+            return f(&mut self.persisted);
+        }
+
+        let _lock = self.lock()?;
+        self.reload().wrap_err("reloading cache")?;
+        f(&mut self.persisted)?;
+        self.flush()
     }
 
     fn set_last_test(&mut self, last_test: impl Into<String>) -> eyre::Result<()> {
         let here = current_dir()?;
-        // TODO
-        self.persisted
-            .last_test
-            .get_or_insert_with(HashMap::new)
-            .insert(here, last_test.into());
-        self.flush().wrap_err("flushing cache changes to disk")?;
+        let last_test = last_test.into();
+        self.update(|persisted| {
+            persisted.push_history(here, last_test);
+            Ok(())
+        })
+        .wrap_err("flushing cache changes to disk")?;
         Ok(())
     }
 
-    fn clear(&mut self, clear_option: CacheClearOption) -> eyre::Result<()> {
-        self.persisted
-            .clear(clear_option)
-            .wrap_err("clearing cache")?;
-        self.flush()?;
-        Ok(())
-    }
+    /// The state as printed by `state show` in `mode`, ending in a newline
+    ///
+    /// Apart from the whole cache, this is empty if no tests were recorded in the current
+    /// directory.
+    fn show(&self, mode: ShowMode) -> eyre::Result<String> {
+        let current_dir = current_dir().wrap_err("getting current directory")?;
+        let json = match (mode, self.persisted.recorded_tests(&current_dir)) {
+            (ShowMode::All, _) => serde_json::to_string_pretty(&self.persisted),
+            (_, None) => return Ok(String::new()),
+            (ShowMode::Plain, Some(tests)) => {
+                return Ok(tests.iter().map(|test| format!("{test}\n")).collect());
+            }
+            (ShowMode::Tests, Some(tests)) => serde_json::to_string_pretty(&tests),
+            (ShowMode::Durations, Some(tests)) => {
+                let entries: Vec<_> = tests
+                    .iter()
+                    .map(|test| HistoryEntry {
+                        node_id: test,
+                        last_duration: self
+                            .persisted
+                            .last_duration(&current_dir, test)
+                            .map(|duration| duration.as_secs_f64()),
+                    })
+                    .collect();
+                serde_json::to_string_pretty(&entries)
+            }
+        }
+        .wrap_err("serializing state to JSON")?;
+        Ok(format!("{json}\n"))
+    }
+
+    /// Record how long each test took to run in the current directory
+    fn record_durations(
+        &mut self,
+        durations: impl IntoIterator<Item = (String, Duration)>,
+    ) -> eyre::Result<()> {
+        let here = current_dir()?;
+        self.update(|persisted| {
+            for (test, duration) in durations {
+                persisted
+                    .durations
+                    .entry(here.clone())
+                    .or_default()
+                    .insert(test.clone(), duration.as_secs_f64());
+                persisted.mark_run(here.clone(), test);
+            }
+            Ok(())
+        })
+        .wrap_err("flushing cache changes to disk")?;
+        Ok(())
+    }
+
+    /// Pin `test` in the current directory, returning whether it was not already pinned
+    fn pin(&mut self, test: String) -> eyre::Result<bool> {
+        let here = current_dir()?;
+        let mut added = false;
+        self.update(|persisted| {
+            added = persisted.pin(here, test);
+            Ok(())
+        })
+        .wrap_err("flushing cache changes to disk")?;
+        Ok(added)
+    }
+
+    /// Unpin `test` in the current directory, returning whether it was pinned
+    fn unpin(&mut self, test: &str) -> eyre::Result<bool> {
+        let here = current_dir()?;
+        let mut removed = false;
+        self.update(|persisted| {
+            removed = persisted.unpin(&here, test);
+            Ok(())
+        })
+        .wrap_err("flushing cache changes to disk")?;
+        Ok(removed)
+    }
+
+    fn clear(&mut self, clear_option: CacheClearOption) -> eyre::Result<()> {
+        self.update(|persisted| persisted.clear(clear_option).wrap_err("clearing cache"))?;
+        Ok(())
+    }
+
+    /// Write the state to disk. Must be called with the cache lock held.
+    fn flush(&self) -> eyre::Result<()> {
+        // write to a temporary file in the same directory and rename it over the cache file, so
+        // an interrupted write can never leave a truncated cache behind
+        let tmp_file = self
+            .cache_file
+            .with_file_name(format!("cache.json.{}.tmp", std::process::id()));
+        let mut outfile = std::fs::File::create(&tmp_file).wrap_err("creating cache file")?;
+        serde_json::to_writer(&mut outfile, &self.persisted)
+            .wrap_err("writing state to cache file")?;
+        outfile.sync_all().wrap_err("syncing cache file")?;
+        std::fs::rename(&tmp_file, &self.cache_file).wrap_err("replacing cache file")?;
+        Ok(())
+    }
+
+    fn migrate_settings(&mut self) -> eyre::Result<()> {
+        self.update(|persisted| persisted.migrate_settings())
+    }
+}
+
+/// A test file found while walking the search roots
+#[derive(Debug, Clone)]
+struct TestFile {
+    path: PathBuf,
+    /// Size of the file in bytes
+    size: u64,
+    /// Last modification time, if supported by the platform
+    modified: Option<SystemTime>,
+}
+
+/// Files which are never tests, skipped when matching custom file patterns
+const DEFAULT_EXCLUDES: &[&str] = &["conftest.py", "setup.py", "__init__.py"];
+
+/// Directories which are never searched for tests, unless `--no-default-exclude-dir` is given
+const DEFAULT_EXCLUDED_DIRS: &[&str] = &[".venv", "__pycache__", ".tox"];
+
+/// Directory names discovery does not descend into
+#[derive(Debug, Clone)]
+struct ExcludedDirs {
+    names: Vec<String>,
+}
+
+impl Default for ExcludedDirs {
+    fn default() -> Self {
+        Self {
+            names: DEFAULT_EXCLUDED_DIRS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl ExcludedDirs {
+    fn from_args(args: &SearchArgs) -> Self {
+        let mut excluded = if args.no_default_exclude_dir {
+            Self { names: Vec::new() }
+        } else {
+            Self::default()
+        };
+        excluded.names.extend(args.exclude_dirs.iter().cloned());
+        excluded
+    }
+
+    /// Whether the walk should skip the directory `entry` and everything below it
+    ///
+    /// A search root is always walked, even if it has an excluded name.
+    fn prunes(&self, entry: &ignore::DirEntry) -> bool {
+        entry.depth() > 0
+            && entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| self.names.iter().any(|excluded| excluded == name))
+    }
+}
+
+/// Decides which files discovery picks up, by file name
+#[derive(Debug, Clone, Default)]
+enum FileMatcher {
+    /// pytest test files, `test_*.py`
+    #[default]
+    Tests,
+    /// Test files and Jupyter notebooks
+    TestsAndNotebooks,
+    /// Files which may define fixtures: test files and `conftest.py`
+    Fixtures,
+    /// Files matching user-provided globs
+    Patterns {
+        globs: GlobSet,
+        /// Skip the [`DEFAULT_EXCLUDES`] even if they match
+        default_excludes: bool,
+    },
+}
+
+impl FileMatcher {
+    /// The matcher for test files, honouring the user's file patterns if given
+    fn for_tests(args: &SearchArgs) -> eyre::Result<Self> {
+        if !args.file_patterns.is_empty() {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in &args.file_patterns {
+                builder.add(
+                    Glob::new(pattern)
+                        .wrap_err_with(|| format!("compiling file pattern: {pattern}"))?,
+                );
+            }
+            return Ok(FileMatcher::Patterns {
+                globs: builder.build().wrap_err("building file patterns")?,
+                default_excludes: !args.no_default_excludes,
+            });
+        }
+
+        if args.notebooks {
+            Ok(FileMatcher::TestsAndNotebooks)
+        } else {
+            Ok(FileMatcher::Tests)
+        }
+    }
+
+    fn matches(&self, filename: &str) -> bool {
+        let is_test_file = filename.starts_with("test_") && filename.ends_with(".py");
+        match self {
+            FileMatcher::Tests => is_test_file,
+            FileMatcher::TestsAndNotebooks => is_test_file || filename.ends_with(".ipynb"),
+            FileMatcher::Fixtures => is_test_file || filename == "conftest.py",
+            FileMatcher::Patterns {
+                globs,
+                default_excludes,
+            } => {
+                // broad patterns such as `*.py` would otherwise pick up package and config files
+                globs.is_match(filename)
+                    && !(*default_excludes && DEFAULT_EXCLUDES.contains(&filename))
+            }
+        }
+    }
+}
+
+/// Ignore file, in gitignore syntax, for excluding paths from test discovery only
+///
+/// Like `.gitignore` it is read in every directory walked, and its patterns take precedence
+/// over `.gitignore` and `.ignore` files, so `!pattern` can bring back git-ignored tests.
+const IGNORE_FILENAME: &str = ".testsearchignore";
+
+/// How discovery walks each search root
+#[derive(Debug, Clone, Default)]
+struct WalkOptions {
+    matcher: FileMatcher,
+    excluded_dirs: ExcludedDirs,
+    /// How many directory levels below the root to descend, or unlimited if `None`
+    max_depth: Option<usize>,
+}
+
+impl WalkOptions {
+    fn from_args(args: &SearchArgs, matcher: FileMatcher) -> Self {
+        Self {
+            matcher,
+            excluded_dirs: ExcludedDirs::from_args(args),
+            max_depth: args.max_depth,
+        }
+    }
+}
+
+/// Walk `root`, sending every test file found to `chan` and returning how many paths could
+/// not be read
+///
+/// Unreadable paths, e.g. directories without permission or broken symlinks, are logged and
+/// skipped rather than ending the walk.
+fn find_test_files(
+    root: impl AsRef<Path>,
+    chan: Sender<TestFile>,
+    options: &WalkOptions,
+) -> eyre::Result<usize> {
+    let errors = AtomicUsize::new(0);
+    WalkBuilder::new(root)
+        .add_custom_ignore_filename(IGNORE_FILENAME)
+        .max_depth(options.max_depth)
+        .build_parallel()
+        .run(|| {
+            Box::new(|path| {
+                let entry = match path {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        // the error message includes the offending path
+                        tracing::warn!(error = %e, "walking test files");
+                        errors.fetch_add(1, Ordering::Relaxed);
+                        return ignore::WalkState::Continue;
+                    }
+                };
+
+                // directories are decided on as a whole, so an excluded subtree is pruned
+                // rather than walked and filtered file by file
+                if entry
+                    .file_type()
+                    .is_some_and(|file_type| file_type.is_dir())
+                {
+                    return if options.excluded_dirs.prunes(&entry) {
+                        ignore::WalkState::Skip
+                    } else {
+                        ignore::WalkState::Continue
+                    };
+                }
+
+                let path = entry.path();
+                let is_test_file = path
+                    .file_name()
+                    .and_then(|filename| filename.to_str())
+                    .map(|filename| options.matcher.matches(filename))
+                    .unwrap_or_default();
+                if !is_test_file {
+                    return ignore::WalkState::Continue;
+                }
+                // the walker's entry gives the file type, size and mtime; a symlink's entry
+                // describes the link, so only then is the target stat-ed
+                let metadata = if entry.path_is_symlink() {
+                    fs::metadata(path).map_err(ignore::Error::from)
+                } else {
+                    entry.metadata()
+                };
+                match metadata {
+                    Ok(metadata) if metadata.is_file() => {
+                        let _ = chan.send(TestFile {
+                            path: path.to_path_buf(),
+                            size: metadata.len(),
+                            modified: metadata.modified().ok(),
+                        });
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::warn!(error = %e, path = %path.display(), "reading test file metadata");
+                        errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                ignore::WalkState::Continue
+            })
+        });
+    Ok(errors.into_inner())
+}
+
+/// Use the provided roots, or default to the current working directory
+fn search_roots(root: Vec<PathBuf>) -> eyre::Result<Vec<PathBuf>> {
+    if root.is_empty() {
+        let here = current_dir()?;
+        Ok(vec![here])
+    } else {
+        Ok(root)
+    }
+}
+
+/// The result of walking the search roots
+struct WalkedFiles {
+    files: Vec<TestFile>,
+    /// Number of paths which could not be read, and so may be hiding tests
+    errors: usize,
+}
+
+/// Whether the file at `path`, of `size` bytes, is no larger than `max_file_size` (if given)
+fn within_size_limit(path: &Path, size: u64, max_file_size: Option<u64>) -> bool {
+    match max_file_size {
+        Some(max_file_size) if size > max_file_size => {
+            tracing::debug!(
+                path = %path.display(),
+                size,
+                max_file_size,
+                "skipping large test file"
+            );
+            false
+        }
+        _ => true,
+    }
+}
+
+/// Walk the search roots in parallel, returning every file accepted by the walk `options`
+///
+/// Files larger than `max_file_size` bytes are skipped.
+fn collect_test_files(
+    search_roots: Vec<PathBuf>,
+    max_file_size: Option<u64>,
+    progress: &ProgressBar,
+    options: Arc<WalkOptions>,
+) -> WalkedFiles {
+    let (files_tx, files_rx) = unbounded();
+
+    let mut file_handles = Vec::new();
+    for path in search_roots {
+        // walking a missing root silently finds nothing, which hides typos
+        if !path.exists() {
+            progress.suspend(|| {
+                eprintln!(
+                    "warning: search root {} does not exist, skipping",
+                    path.display()
+                )
+            });
+            continue;
+        }
+
+        let span = tracing::debug_span!("", path = %path.display());
+        let _guard = span.enter();
+
+        tracing::debug!("listing files");
+
+        // every root shares the options, whose matcher is compiled once by the caller
+        let files_tx = files_tx.clone();
+        let options = Arc::clone(&options);
+        file_handles.push(thread::spawn(move || {
+            match find_test_files(&path, files_tx, &options) {
+                Ok(errors) => errors,
+                Err(e) => {
+                    tracing::warn!(error = %e, path = %path.display(), "finding test files");
+                    0
+                }
+            }
+        }));
+    }
+    drop(files_tx);
+
+    // drain the channel while the walkers run so the progress count stays live
+    let found: Vec<TestFile> = files_rx
+        .into_iter()
+        .inspect(|_| {
+            progress.inc(1);
+            progress.set_message(format!("finding test files: {}", progress.position()));
+        })
+        .collect();
+
+    let errors: usize = file_handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap_or_default())
+        .sum();
+    if errors > 0 {
+        progress.suspend(|| {
+            eprintln!(
+                "warning: {errors} path(s) could not be read while searching for tests, \
+                 set RUST_LOG=warn for details"
+            )
+        });
+    }
+
+    let files: Vec<TestFile> = found
+        .into_iter()
+        .filter(|file| within_size_limit(&file.path, file.size, max_file_size))
+        .collect();
+    for file in &files {
+        tracing::trace!(
+            path = %file.path.display(),
+            size = file.size,
+            modified = ?file.modified,
+            "found test file"
+        );
+    }
+    tracing::debug!(
+        n = files.len(),
+        bytes = files.iter().map(|file| file.size).sum::<u64>(),
+        "finished collecting files"
+    );
+    WalkedFiles { files, errors }
+}
+
+/// Walk the search roots in parallel, returning the path of every test file found
+fn collect_test_paths(
+    search_roots: Vec<PathBuf>,
+    max_file_size: Option<u64>,
+    progress: &ProgressBar,
+) -> Vec<PathBuf> {
+    collect_test_files(
+        search_roots,
+        max_file_size,
+        progress,
+        Arc::new(WalkOptions::default()),
+    )
+    .files
+    .into_iter()
+    .map(|file| file.path)
+    .collect()
+}
+
+/// Find the files to parse, either from an explicit list or by walking the search roots
+fn find_files(args: &SearchArgs, progress: &ProgressBar) -> eyre::Result<Vec<PathBuf>> {
+    if let Some(source) = &args.files_from {
+        return read_file_list(source, args.max_file_size);
+    }
+
+    let walked = collect_test_files(
+        search_roots(args.root.clone())?,
+        args.max_file_size,
+        progress,
+        Arc::new(WalkOptions::from_args(args, FileMatcher::for_tests(args)?)),
+    );
+    if args.timing {
+        progress.suspend(|| eprintln!("walk errors: {}", walked.errors));
+    }
+    Ok(walked.files.into_iter().map(|file| file.path).collect())
+}
+
+/// Read the non-blank lines of `source`, or stdin if it is `-`, with surrounding whitespace
+/// trimmed
+fn read_lines(source: &Path) -> eyre::Result<Vec<String>> {
+    let reader: Box<dyn BufRead> = if source == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        let f = fs::File::open(source).wrap_err_with(|| format!("opening {}", source.display()))?;
+        Box::new(BufReader::new(f))
+    };
+
+    let mut lines = Vec::new();
+    for line in reader.lines() {
+        let line = line.wrap_err_with(|| format!("reading {}", source.display()))?;
+        let line = line.trim();
+        if !line.is_empty() {
+            lines.push(line.to_string());
+        }
+    }
+    Ok(lines)
+}
+
+/// Read a newline-delimited list of files to parse from `source`, or stdin if it is `-`
+///
+/// Blank lines are ignored, and paths that are not files are skipped with a warning. Files larger
+/// than `max_file_size` bytes are skipped, as when walking the search roots.
+fn read_file_list(source: &Path, max_file_size: Option<u64>) -> eyre::Result<Vec<PathBuf>> {
+    let lines = read_lines(source).wrap_err("reading file list")?;
+
+    let mut files = Vec::new();
+    for line in lines {
+        let path = PathBuf::from(line);
+        let Some(metadata) = fs::metadata(&path)
+            .ok()
+            .filter(|metadata| metadata.is_file())
+        else {
+            tracing::warn!(path = %path.display(), "skipping missing file from file list");
+            continue;
+        };
+        if within_size_limit(&path, metadata.len(), max_file_size) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Parse the test files in parallel, returning a channel of the discovered tests
+///
+/// With node ids relative to a rootdir, files whose node ids would clash with those of another
+/// file are skipped, see `skip_colliding_node_ids`.
+fn collect_tests(
+    files: Vec<PathBuf>,
+    options: &ParseOptions,
+    progress: &ProgressBar,
+) -> Receiver<Arc<dyn SkimItem>> {
+    let files = match options.rootdir {
+        Some(rootdir) => skip_colliding_node_ids(files, rootdir, progress),
+        None => files,
+    };
+    let n_files = files.len();
+    progress.set_position(0);
+
+    let (test_tx, test_rx) = unbounded();
+    files
+        .into_par_iter()
+        .for_each_with(test_tx, |sender, path| {
+            if let Err(e) = parse_file_with_options(sender, &path, options) {
+                // skipping a file is worth knowing about even without RUST_LOG
+                if let Some(unsupported) = e
+                    .chain()
+                    .find_map(|cause| cause.downcast_ref::<UnsupportedEncoding>())
+                {
+                    progress.suspend(|| eprintln!("warning: {}: {unsupported}", path.display()));
+                    return;
+                }
+                tracing::warn!(error = %e, path = %path.display(), "error parsing file");
+            }
+            // the channel is unbounded and not read until parsing finishes, so its length is the
+            // number of tests found so far
+            progress.inc(1);
+            progress.set_message(format!(
+                "parsing test files: {}/{n_files}, {} tests",
+                progress.position(),
+                sender.len()
+            ));
+        });
+    test_rx
+}
+
+/// Create a spinner reporting discovery and parsing progress on stderr
+///
+/// The spinner is hidden with `--quiet` or when stderr is not a terminal. It must be cleared with
+/// [`ProgressBar::finish_and_clear`] before the fuzzy finder takes over the terminal.
+fn progress_spinner() -> ProgressBar {
+    if is_quiet() || !io::stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+
+    let progress = ProgressBar::new_spinner();
+    progress.set_style(
+        ProgressStyle::with_template("{spinner} {msg} [{elapsed}]")
+            .expect("valid progress template"),
+    );
+    progress.enable_steady_tick(Duration::from_millis(100));
+    progress
+}
+
+/// Exit code used when the user aborts an interactive search, matching `fzf`
+const ABORT_EXIT_CODE: u8 = 130;
+
+/// Result of running a search
+#[derive(Debug)]
+enum SearchOutcome {
+    /// The user selected a test
+    Selected(TestId),
+    /// Results were printed rather than interactively selected
+    Printed,
+    /// The user opened the selected test in their editor
+    Edited,
+    /// The user aborted the interactive search
+    Aborted,
+    /// The interactive search finished without a selection
+    NoSelection,
+}
+
+impl SearchOutcome {
+    fn exit_code(&self) -> ExitCode {
+        match self {
+            SearchOutcome::Aborted => ExitCode::from(ABORT_EXIT_CODE),
+            // no test selected is not an error
+            _ => ExitCode::SUCCESS,
+        }
+    }
+}
+
+fn perform_search(
+    args: SearchArgs,
+    finder: &Finder,
+    color: ColorMode,
+    state: &mut State,
+) -> eyre::Result<SearchOutcome> {
+    // clap can only make whole arguments conflict, not one value of `--format`
+    if args.null && matches!(args.format, OutputFormat::Json) {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "the argument '--format json' cannot be used with '--null'",
+            )
+            .exit();
+    }
+
+    let progress = progress_spinner();
+
+    let walk_start = Instant::now();
+    let files = find_files(&args, &progress)?;
+    let rootdir = node_id_rootdir(&args)?;
+    let location = search_location(&args)?;
+    if files.is_empty() {
+        progress.finish_and_clear();
+        eyre::bail!("No compatible test files found");
+    }
+    progress.suspend(|| report_timing(args.timing, "walking files", walk_start));
+
+    let parse_start = Instant::now();
+    let mut options = args.parse_options(rootdir.as_deref());
+    options.style.dim_path = color.enabled();
+    options.durations = state.persisted.durations.get(&current_dir()?);
+    let test_rx = collect_tests(files, &options, &progress);
+    let SearchArgs {
+        no_fuzzy_selection,
+        first,
+        timing,
+        verbose,
+        sort,
+        no_history_boost,
+        limit,
+        null,
+        select_one,
+        format,
+        selection_file,
+        ..
+    } = args;
+    // parsing has finished, so clear the spinner before anything else uses the terminal
+    progress.finish_and_clear();
+    report_timing(timing, "parsing files", parse_start);
+
+    if no_fuzzy_selection {
+        let mut tests: Vec<_> = test_rx.into_iter().collect();
+        sort_tests(&mut tests);
+        truncate_tests(&mut tests, limit);
+        match format {
+            OutputFormat::NodeId => write_node_ids(&mut io::stdout().lock(), &tests, null)?,
+            OutputFormat::Quickfix => write_quickfix(&mut io::stdout().lock(), &tests, null)?,
+            OutputFormat::Json => write_json(&mut io::stdout().lock(), &tests)?,
+            OutputFormat::LineRange => write_line_ranges(&mut io::stdout().lock(), &tests, null)?,
+        }
+
+        return Ok(SearchOutcome::Printed);
+    }
+
+    if first {
+        let mut tests: Vec<_> = test_rx.into_iter().collect();
+        sort_tests(&mut tests);
+        let Some(test) = tests.first() else {
+            eyre::bail!("No tests match the given filters");
+        };
+        return select_test(test.as_ref(), state, selection_file.as_deref(), null);
+    }
+
+    // limiting the results needs a stable order so the same tests are kept every time
+    let test_rx = if sort || limit.is_some() {
+        sorted(test_rx, limit)
+    } else {
+        test_rx
+    };
+
+    // previously run tests are the most likely picks, so put them first
+    let test_rx = match state.persisted.history(current_dir()?) {
+        Some(history) if !no_history_boost => history_first(test_rx, &history),
+        _ => test_rx,
+    };
+
+    // a name filter matching a single test selects it without opening the fuzzy finder, if asked
+    if select_one
+        && test_rx.len() == 1
+        && let Ok(test) = test_rx.recv()
+    {
+        return select_test(test.as_ref(), state, selection_file.as_deref(), null);
+    }
+
+    // parsing has finished, so every test is already in the channel
+    let header = format!("{} tests in {location}", test_rx.len());
+    let mut skim_options = finder.options(&header);
+    // an empty preview command makes skim ask each test for its own preview
+    skim_options.preview = Some("");
+    skim_options.preview_window = Some("down:30%:wrap");
+
+    // perform fuzzy search
+    let skim_start = Instant::now();
+    let search_result = skim::Skim::run_with(&skim_options, Some(test_rx))
+        .ok_or_else(|| eyre::eyre!("performing interactive search"))?;
+    report_timing(timing, "interactive search", skim_start);
+
+    if search_result.is_abort {
+        tracing::info!("search aborted");
+        if verbose {
+            eprintln!("search aborted");
+        }
+        return Ok(SearchOutcome::Aborted);
+    }
+
+    let selected_items = search_result.selected_items;
+
+    if selected_items.is_empty() {
+        tracing::info!("no tests selected");
+        if verbose {
+            eprintln!("no tests selected");
+        }
+        return Ok(SearchOutcome::NoSelection);
+    }
+
+    if selected_items.len() > 1 {
+        panic!("programming error: multiple tests selected");
+    }
+
+    if search_result.final_key == Key::Ctrl('e') {
+        if let Some(test_case) = selected_items[0].as_any().downcast_ref::<TestCase>() {
+            open_in_editor(&test_case.file, test_case.line)?;
+        }
+        return Ok(SearchOutcome::Edited);
+    }
+
+    select_test(
+        selected_items[0].as_ref(),
+        state,
+        selection_file.as_deref(),
+        null,
+    )
+}
+
+/// Pick a file, class or test from a tree of the discovered tests
+fn browse_tests(args: SearchArgs, state: &mut State) -> eyre::Result<SearchOutcome> {
+    let location = search_location(&args)?;
+    let progress = progress_spinner();
+    let files = find_files(&args, &progress)?;
+    if files.is_empty() {
+        progress.finish_and_clear();
+        eyre::bail!("No compatible test files found");
+    }
+
+    let rootdir = node_id_rootdir(&args)?;
+    let options = args.parse_options(rootdir.as_deref());
+    let mut tests: Vec<_> = collect_tests(files, &options, &progress)
+        .into_iter()
+        .collect();
+    progress.finish_and_clear();
+    sort_tests(&mut tests);
+    truncate_tests(&mut tests, args.limit);
+
+    let title = format!("{} tests in {location}", tests.len());
+    let Some(node_id) = browse::browse(&tests, &title)? else {
+        tracing::info!("browse aborted");
+        return Ok(SearchOutcome::Aborted);
+    };
+    let node_id = node_id.parse().map_err(|e| eyre::eyre!("{e}"))?;
+    finish_selection(node_id, state, args.selection_file.as_deref(), args.null)
+}
+
+/// Record `test` as the last test run and print its node id
+fn select_test(
+    test: &dyn SkimItem,
+    state: &mut State,
+    selection_file: Option<&Path>,
+    null: bool,
+) -> eyre::Result<SearchOutcome> {
+    let node_id = match test.as_any().downcast_ref::<TestCase>() {
+        Some(test_case) => {
+            let node_id = test_case.id();
+            tracing::debug!(
+                test = %node_id,
+                framework = ?test_case.framework,
+                markers = ?test_case.markers,
+                "selected test"
+            );
+            node_id
+        }
+        None => test.output().parse().map_err(|e| eyre::eyre!("{e}"))?,
+    };
+    finish_selection(node_id, state, selection_file, null)
+}
+
+/// Record `node_id` as the last test run, then print it and write it to `selection_file`
+///
+/// The node id, terminated by a newline or a NUL byte if `null` is set, is the only thing a
+/// selection writes to stdout, so it can be captured with `$(testsearch)`.
+fn finish_selection(
+    node_id: TestId,
+    state: &mut State,
+    selection_file: Option<&Path>,
+    null: bool,
+) -> eyre::Result<SearchOutcome> {
+    state.set_last_test(node_id.to_string())?;
+    let terminator = if null { '\0' } else { '\n' };
+    let mut stdout = io::stdout().lock();
+    write!(stdout, "{node_id}{terminator}")?;
+    stdout.flush()?;
+    if let Some(selection_file) = selection_file {
+        write_selection_file(selection_file, &node_id)
+            .wrap_err_with(|| format!("writing selection file {}", selection_file.display()))?;
+    }
+    Ok(SearchOutcome::Selected(node_id))
+}
+
+/// Write `node_id` to `path` via a temporary file in the same directory, so readers never see
+/// a partially written selection
+fn write_selection_file(path: &Path, node_id: &TestId) -> eyre::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| eyre::eyre!("not a file path"))?;
+    let tmp_file = path.with_file_name(format!(
+        "{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    fs::write(&tmp_file, format!("{node_id}\n")).wrap_err("writing temporary file")?;
+    fs::rename(&tmp_file, path).wrap_err("replacing selection file")?;
+    Ok(())
+}
+
+/// Open `file` at `line` in the user's `$VISUAL` or `$EDITOR`, falling back to `vi`
+fn open_in_editor(file: &Path, line: usize) -> eyre::Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    // the editor may include arguments, e.g. `code --wait`
+    let mut parts = editor.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| eyre::eyre!("empty editor command"))?;
+
+    let mut command = std::process::Command::new(program);
+    command.args(parts).arg(format!("+{line}")).arg(file);
+    // when stdout is being captured, e.g. by `$(testsearch)`, the editor must not draw into it
+    if !io::stdout().is_terminal() {
+        command.stdout(io::stderr());
+    }
+    let status = command
+        .status()
+        .wrap_err_with(|| format!("launching editor {editor}"))?;
+    if !status.success() {
+        eyre::bail!("editor exited with {status}");
+    }
+    Ok(())
+}
+
+/// Print the time elapsed since `start` for the given phase, if timing output is enabled
+fn report_timing(enabled: bool, phase: &str, start: Instant) {
+    if enabled {
+        eprintln!("{phase}: {:.2?}", start.elapsed());
+    }
+}
+
+/// How tests are printed when not using the fuzzy finder
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+enum OutputFormat {
+    /// One node id per line
+    #[default]
+    NodeId,
+    /// `file:line:col: name` lines for Vim's quickfix list
+    Quickfix,
+    /// One JSON object per line, with the node id, location, marks and docstring of each test
+    Json,
+    /// `node_id@start-end` lines, with the 1-based, inclusive lines spanned by each test
+    LineRange,
+}
+
+fn perform_grep_search(args: GrepArgs, state: &mut State) -> eyre::Result<ExitCode> {
+    let GrepArgs {
+        pattern,
+        patterns,
+        all,
+        any: _,
+        run: run_command,
+        runner,
+        remote,
+        addopts,
+        fail_fast,
+        output_dir,
+        junit,
+        shuffle,
+        invert_match,
+        show_match,
+        scope,
+        search_args,
+    } = args;
+    let patterns: Vec<String> = pattern.into_iter().chain(patterns).collect();
+
+    // Compile the regex patterns
+    let regexes = patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).wrap_err_with(|| format!("compiling regex pattern: {}", pattern))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    let filter = GrepFilter {
+        regexes,
+        require_all: all,
+        invert: invert_match,
+        show_match,
+        scope,
+    };
+
+    // project files are looked for near the first search root
+    let run_root = match (&search_args.files_from, search_args.root.first()) {
+        (None, Some(root)) => root.clone(),
+        _ => current_dir()?,
+    };
+
+    let progress = progress_spinner();
+    let files = find_files(&search_args, &progress)?;
+    if files.is_empty() {
+        progress.finish_and_clear();
+        eyre::bail!("No compatible test files found");
+    }
+
+    let rootdir = node_id_rootdir(&search_args)?;
+    let test_rx = collect_tests(
+        files,
+        &ParseOptions {
+            filter: Some(&filter),
+            ..search_args.parse_options(rootdir.as_deref())
+        },
+        &progress,
+    );
+    progress.finish_and_clear();
+    let mut matching_tests: Vec<_> = test_rx.into_iter().collect();
+    sort_tests(&mut matching_tests);
+    truncate_tests(&mut matching_tests, search_args.limit);
+
+    if matching_tests.is_empty() {
+        status!("No tests found matching pattern: {}\n", patterns.join(", "));
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Print all matching test node IDs
+    if search_args.null {
+        write_node_ids(&mut io::stdout().lock(), &matching_tests, true)?;
+    } else {
+        for test in &matching_tests {
+            println!("{}", test.output());
+            if let Some(test_case) = test.as_any().downcast_ref::<TestCase>() {
+                for line in &test_case.matched_lines {
+                    println!("{}:{}", line.line_number, line.text);
+                }
+            }
+        }
+    }
+
+    // If run command is provided, execute the tests
+    if let Some(command_template) = run_command {
+        let (command_template, unittest_names) = match command_template {
+            Some(command_template) => (command_template, false),
+            None => {
+                let (command_template, unittest_names) =
+                    default_run_command(&run_root, || unittest_only(&matching_tests));
+                if !is_quiet() {
+                    eprintln!("Using inferred command template: {command_template}");
+                }
+                (command_template, unittest_names)
+            }
+        };
+        let command_template = build_command_template(
+            command_template,
+            runner,
+            addopts.as_deref(),
+            remote.as_ref(),
+        );
+        status!("\nExecuting matching tests...\n\n");
+
+        let node_ids = matching_tests
+            .iter()
+            .filter_map(|test| test.as_any().downcast_ref::<TestCase>())
+            .map(TestCase::id);
+        let options = RunOptions {
+            fail_fast,
+            output_dir,
+            junit,
+            shuffle,
+            remote,
+            unittest_names,
+        };
+        return run_tests(&command_template, node_ids, &options, state);
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// How `run_tests` runs a batch of tests
+#[derive(Debug, Default)]
+struct RunOptions {
+    /// Stop at the first failing test
+    fail_fast: bool,
+    /// Also write the output of each test to a log file in this directory
+    output_dir: Option<PathBuf>,
+    /// Write a JUnit XML report of the run to this file
+    junit: Option<PathBuf>,
+    /// Run the tests in a random order, from this seed or a random one
+    shuffle: Option<Option<u64>>,
+    /// Run the tests on this host over ssh
+    remote: Option<Remote>,
+    /// Give the command unittest's dotted test names rather than node ids, as the inferred
+    /// `python -m unittest` template needs
+    unittest_names: bool,
+}
+
+/// Set by the Ctrl-C handler installed by `run_tests`
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// How long an interrupted test command has to exit by itself, e.g. for pytest to print its
+/// summary, before it is killed
+const INTERRUPT_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// Handle Ctrl-C by flagging the interruption rather than exiting, so a batch of tests can stop
+/// its running test command and restore the terminal instead of leaving both behind
+fn install_interrupt_handler() {
+    if let Err(e) = ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::SeqCst)) {
+        tracing::warn!(error = %e, "installing Ctrl-C handler");
+    }
+}
+
+fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Undo what an interrupted test command or a panic may have left on the terminal: raw mode, a
+/// hidden cursor or a colour still in effect
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    if io::stdout().is_terminal() {
+        let _ = crossterm::execute!(
+            io::stdout(),
+            crossterm::style::ResetColor,
+            crossterm::cursor::Show
+        );
+    }
+}
+
+/// Run each test in turn with `command_template`
+///
+/// Failures are reported but do not stop the run unless `fail_fast` is set, in which case the
+/// run stops at the first failure and fails. Ctrl-C stops the run after the current test, whose
+/// command is killed if it does not exit by itself. The duration of every test that ran is
+/// recorded in the cache. When shuffling, the seed is printed so the order can be reproduced.
+fn run_tests(
+    command_template: &str,
+    node_ids: impl IntoIterator<Item = TestId>,
+    options: &RunOptions,
+    state: &mut State,
+) -> eyre::Result<ExitCode> {
+    if let Some(output_dir) = &options.output_dir {
+        fs::create_dir_all(output_dir)
+            .wrap_err_with(|| format!("creating output directory {}", output_dir.display()))?;
+    }
+
+    let mut node_ids: Vec<_> = node_ids.into_iter().collect();
+    if let Some(seed) = options.shuffle {
+        let seed = seed.unwrap_or_else(|| fastrand::u64(..));
+        status!("Shuffling tests with seed {seed}\n");
+        fastrand::Rng::with_seed(seed).shuffle(&mut node_ids);
+    }
+
+    install_interrupt_handler();
+    let total = node_ids.len();
+    let mut runs = Vec::new();
+    for node_id in node_ids {
+        let log_file = options
+            .output_dir
+            .as_ref()
+            .map(|output_dir| output_dir.join(log_file_name(&node_id)));
+        let start = Instant::now();
+        let test = remote_test_id(&node_id, options.remote.as_ref());
+        let outcome = match execute_test_command(
+            command_template,
+            options.unittest_names,
+            options.remote.is_some(),
+            &test,
+            log_file.as_deref(),
+        ) {
+            Ok(status) if status.success() => RunOutcome::Passed,
+            Ok(status) => RunOutcome::Failed(status.code()),
+            Err(e) => {
+                eprintln!("❌ Execution failed for {}: {}", node_id, e);
+                RunOutcome::Error(format!("{e:#}"))
+            }
+        };
+        // an interrupted test did not get to finish, whatever its exit status
+        let outcome = if is_interrupted() {
+            RunOutcome::Error("interrupted".to_string())
+        } else {
+            outcome
+        };
+        let duration = start.elapsed();
+        if let Some(log_file) = &log_file {
+            status!("Output written to {}\n", log_file.display());
+        }
+
+        let passed = matches!(outcome, RunOutcome::Passed);
+        runs.push(TestRun {
+            node_id,
+            duration,
+            outcome,
+        });
+        // an interrupted test also did not pass, so check this before --fail-fast stops the run
+        if is_interrupted() {
+            restore_terminal();
+            eprintln!(
+                "Interrupted, skipping the remaining {} test(s)",
+                total - runs.len()
+            );
+            break;
+        }
+        if options.fail_fast && !passed {
+            eprintln!(
+                "Stopping after first failure: {}",
+                runs[runs.len() - 1].node_id
+            );
+            break;
+        }
+    }
+
+    // tests that could not be run at all have no meaningful duration
+    let durations = runs
+        .iter()
+        .filter(|run| !matches!(run.outcome, RunOutcome::Error(_)))
+        .map(|run| (run.node_id.to_string(), run.duration));
+    if let Err(e) = state.record_durations(durations) {
+        tracing::warn!(error = %e, "could not record test durations");
+    }
+
+    if let Some(junit) = &options.junit {
+        write_junit(junit, &runs)
+            .wrap_err_with(|| format!("writing JUnit report {}", junit.display()))?;
+        status!("JUnit report written to {}\n", junit.display());
+    }
+
+    let passed = runs
+        .iter()
+        .filter(|run| matches!(run.outcome, RunOutcome::Passed))
+        .count();
+    let errors = runs
+        .iter()
+        .filter(|run| matches!(run.outcome, RunOutcome::Error(_)))
+        .count();
+    status!(
+        "\n{passed} passed, {} failed, {errors} error(s)\n",
+        runs.len() - passed - errors
+    );
+
+    if is_interrupted() {
+        Ok(ExitCode::from(ABORT_EXIT_CODE))
+    } else if passed < runs.len() {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// The result of running a single test in `run_tests`
+struct TestRun {
+    node_id: TestId,
+    duration: Duration,
+    outcome: RunOutcome,
+}
+
+enum RunOutcome {
+    Passed,
+    /// The test command exited unsuccessfully, with this exit code unless killed by a signal
+    Failed(Option<i32>),
+    /// The test command could not be run
+    Error(String),
+}
+
+/// Write a JUnit XML report of `runs` to `path`
+///
+/// Only the exit status of each test command is known, so a failure records the exit code
+/// rather than the assertion that failed.
+fn write_junit(path: &Path, runs: &[TestRun]) -> eyre::Result<()> {
+    let mut out = io::BufWriter::new(fs::File::create(path)?);
+    let failures = runs
+        .iter()
+        .filter(|run| matches!(run.outcome, RunOutcome::Failed(_)))
+        .count();
+    let errors = runs
+        .iter()
+        .filter(|run| matches!(run.outcome, RunOutcome::Error(_)))
+        .count();
+    let time: f64 = runs.iter().map(|run| run.duration.as_secs_f64()).sum();
+
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, "<testsuites>")?;
+    writeln!(
+        out,
+        r#"  <testsuite name="testsearch" tests="{}" failures="{failures}" errors="{errors}" time="{time:.3}">"#,
+        runs.len()
+    )?;
+    for run in runs {
+        let (classname, name) = junit_names(&run.node_id);
+        write!(
+            out,
+            r#"    <testcase classname="{}" name="{}" time="{:.3}""#,
+            xml_escape(&classname),
+            xml_escape(&name),
+            run.duration.as_secs_f64()
+        )?;
+        let (element, message) = match &run.outcome {
+            RunOutcome::Passed => {
+                writeln!(out, "/>")?;
+                continue;
+            }
+            RunOutcome::Failed(Some(code)) => ("failure", format!("exit code {code}")),
+            RunOutcome::Failed(None) => ("failure", "terminated by a signal".to_string()),
+            RunOutcome::Error(message) => ("error", message.clone()),
+        };
+        writeln!(out, ">")?;
+        writeln!(
+            out,
+            r#"      <{element} message="{}"/>"#,
+            xml_escape(&message)
+        )?;
+        writeln!(out, "    </testcase>")?;
+    }
+    writeln!(out, "  </testsuite>")?;
+    writeln!(out, "</testsuites>")?;
+    out.flush()?;
+    Ok(())
+}
+
+/// The JUnit class name and test name pytest would report for a node id, e.g.
+/// `tests.test_foo.TestBar` and `test_baz[1]`
+fn junit_names(node_id: &TestId) -> (String, String) {
+    let classname = std::iter::once(unittest_module(&node_id.file))
+        .chain(node_id.classes.iter().cloned())
+        .collect::<Vec<_>>()
+        .join(".");
+    let name = match node_id.item_name() {
+        Some(item_name) => item_name.into_owned(),
+        None => node_id.to_string(),
+    };
+    (classname, name)
+}
+
+/// Escape `text` for use in an XML attribute
+fn xml_escape(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// A file name for the log of the test `node_id`, e.g. `tests%2Ftest_foo.py%3A%3Atest_bar.log`
+///
+/// Characters which are not safe in file names (and `%` itself) are percent-encoded, so distinct
+/// node ids such as `tests/test_foo.py` and `tests_test_foo.py` never share a log file.
+fn log_file_name(node_id: &TestId) -> String {
+    let mut name = String::new();
+    for c in node_id.to_string().chars() {
+        if c.is_alphanumeric() || matches!(c, '.' | '-' | '_' | '[' | ']') {
+            name.push(c);
+        } else {
+            for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                name.push_str(&format!("%{byte:02X}"));
+            }
+        }
+    }
+    format!("{name}.log")
+}
+
+/// Run the tests listed, one node id per line, in `source` (or stdin if it is `-`)
+fn run_from_file(
+    source: &Path,
+    command_template: Option<String>,
+    runner: Runner,
+    addopts: Option<&str>,
+    options: &RunOptions,
+    state: &mut State,
+) -> eyre::Result<ExitCode> {
+    let node_ids = read_lines(source)
+        .wrap_err("reading node ids")?
+        .iter()
+        .map(|line| line.parse().map_err(|e| eyre::eyre!("{e}")))
+        .collect::<eyre::Result<Vec<TestId>>>()?;
+    if node_ids.is_empty() {
+        eyre::bail!("No node ids found in {}", source.display());
+    }
+
+    let command_template = match command_template {
+        Some(command_template) => command_template,
+        None => {
+            // without parsing the tests we cannot tell if they are all unittest tests, so this is
+            // always pytest
+            let (command_template, _) = default_run_command(&current_dir()?, || false);
+            if !is_quiet() {
+                eprintln!("Using inferred command template: {command_template}");
+            }
+            command_template
+        }
+    };
+    run_tests(
+        &build_command_template(command_template, runner, addopts, options.remote.as_ref()),
+        node_ids,
+        options,
+        state,
+    )
+}
+
+fn perform_fixture_search(args: SearchArgs, finder: &Finder) -> eyre::Result<ExitCode> {
+    let location = search_location(&args)?;
+    let progress = progress_spinner();
+    let files = match &args.files_from {
+        Some(source) => read_file_list(source, args.max_file_size)?,
+        None => collect_test_files(
+            search_roots(args.root.clone())?,
+            args.max_file_size,
+            &progress,
+            Arc::new(WalkOptions::from_args(&args, FileMatcher::Fixtures)),
+        )
+        .files
+        .into_iter()
+        .map(|file| file.path)
+        .collect(),
+    };
+    if files.is_empty() {
+        progress.finish_and_clear();
+        eyre::bail!("No compatible test files found");
+    }
+
+    let options = ParseOptions {
+        fixtures: true,
+        ..args.parse_options(None)
+    };
+    let fixture_rx = collect_tests(files, &options, &progress);
+    progress.finish_and_clear();
+
+    if args.no_fuzzy_selection {
+        let mut fixtures: Vec<_> = fixture_rx.into_iter().collect();
+        sort_tests(&mut fixtures);
+        truncate_tests(&mut fixtures, args.limit);
+        write_node_ids(&mut io::stdout().lock(), &fixtures, args.null)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let fixture_rx = if args.sort || args.limit.is_some() {
+        sorted(fixture_rx, args.limit)
+    } else {
+        fixture_rx
+    };
+
+    let header = format!("{} fixtures in {location}", fixture_rx.len());
+    let skim_options = finder.options(&header);
+    let search_result = skim::Skim::run_with(&skim_options, Some(fixture_rx))
+        .ok_or_else(|| eyre::eyre!("performing interactive search"))?;
+    if search_result.is_abort {
+        tracing::info!("search aborted");
+        return Ok(SearchOutcome::Aborted.exit_code());
+    }
+
+    let Some(selected) = search_result.selected_items.first() else {
+        tracing::info!("no fixture selected");
+        return Ok(ExitCode::SUCCESS);
+    };
+    match selected.as_any().downcast_ref::<Fixture>() {
+        Some(fixture) => println!("{}:{}", fixture.file.display(), fixture.line),
+        None => println!("{}", selected.output()),
+    }
+    Ok(ExitCode::SUCCESS)
+}
 
-    fn flush(&self) -> eyre::Result<()> {
-        let mut outfile =
-            std::fs::File::create(&self.cache_file).wrap_err("creating cache file")?;
-        serde_json::to_writer(&mut outfile, &self.persisted)
-            .wrap_err("writing state to cache file")?;
-        Ok(())
+/// Write the node id of each test to `writer`, terminated by a newline, or a NUL byte if `null` is
+/// set
+fn write_node_ids(
+    writer: &mut impl Write,
+    tests: &[Arc<dyn SkimItem>],
+    null: bool,
+) -> eyre::Result<()> {
+    let terminator = if null { b"\0" } else { b"\n" };
+    for test in tests {
+        writer
+            .write_all(test.output().as_bytes())
+            .and_then(|_| writer.write_all(terminator))
+            .wrap_err("writing node id")?;
     }
+    writer.flush().wrap_err("flushing node ids")?;
+    Ok(())
+}
 
-    fn migrate_settings(&mut self) -> eyre::Result<()> {
-        self.persisted.migrate_settings()?;
-        self.flush()?;
-        Ok(())
+/// Write each test as its node id followed by the lines its definition spans, including any
+/// decorators, e.g. `test_foo.py::test_bar@12-20`, for editor plugins which run tests by line
+fn write_line_ranges(
+    writer: &mut impl Write,
+    tests: &[Arc<dyn SkimItem>],
+    null: bool,
+) -> eyre::Result<()> {
+    let terminator = if null { '\0' } else { '\n' };
+    for test in tests {
+        let Some(test_case) = test.as_any().downcast_ref::<TestCase>() else {
+            continue;
+        };
+        write!(
+            writer,
+            "{test_case}@{}-{}{terminator}",
+            test_case.lines.start(),
+            test_case.lines.end()
+        )
+        .wrap_err("writing line range")?;
     }
+    writer.flush().wrap_err("flushing line ranges")?;
+    Ok(())
 }
 
-fn find_test_files(root: impl AsRef<Path>, chan: Sender<PathBuf>) -> eyre::Result<()> {
-    WalkBuilder::new(root).build_parallel().run(|| {
-        Box::new(|path| {
-            if let Ok(entry) = path {
-                let path = entry.path();
-                if path.is_file()
-                    && path
-                        .file_name()
-                        .and_then(|filename| filename.to_str())
-                        .map(|filename| filename.starts_with("test_") && filename.ends_with(".py"))
-                        .unwrap_or_default()
-                {
-                    let _ = chan.send(path.to_path_buf());
-                }
-            }
-            ignore::WalkState::Continue
-        })
-    });
+/// A test as printed by `--format json`
+#[derive(Serialize)]
+struct JsonTest<'a> {
+    node_id: String,
+    file: &'a Path,
+    line: usize,
+    column: usize,
+    markers: &'a [String],
+    docstring: Option<&'a str>,
+}
+
+/// Write a JSON object describing each test, one per line
+fn write_json(writer: &mut impl Write, tests: &[Arc<dyn SkimItem>]) -> eyre::Result<()> {
+    for test in tests {
+        let Some(test_case) = test.as_any().downcast_ref::<TestCase>() else {
+            continue;
+        };
+        let json_test = JsonTest {
+            node_id: test_case.to_string(),
+            file: &test_case.file,
+            line: test_case.line,
+            column: test_case.column,
+            markers: &test_case.markers,
+            docstring: test_case.docstring.as_deref(),
+        };
+        serde_json::to_writer(&mut *writer, &json_test).wrap_err("writing JSON entry")?;
+        writeln!(writer).wrap_err("writing JSON entry")?;
+    }
+    writer.flush().wrap_err("flushing JSON entries")?;
     Ok(())
 }
 
-fn perform_search(
+/// Write each test as a `file:line:col: name` line, which Vim's default `errorformat` reads into
+/// the quickfix list
+///
+/// Paths are made relative to the current directory so Vim opens the right buffer.
+fn write_quickfix(
+    writer: &mut impl Write,
+    tests: &[Arc<dyn SkimItem>],
+    null: bool,
+) -> eyre::Result<()> {
+    let here = current_dir()?;
+    let terminator = if null { '\0' } else { '\n' };
+    for test in tests {
+        let Some(test_case) = test.as_any().downcast_ref::<TestCase>() else {
+            continue;
+        };
+        let file = test_case
+            .file
+            .strip_prefix(&here)
+            .unwrap_or(&test_case.file);
+        let mut name = test_case.classes.clone();
+        name.push(test_case.item_name().into_owned());
+        write!(
+            writer,
+            "{}:{}:{}: {}{terminator}",
+            file.display(),
+            test_case.line,
+            test_case.column,
+            name.join("::")
+        )
+        .wrap_err("writing quickfix entry")?;
+    }
+    writer.flush().wrap_err("flushing quickfix entries")?;
+    Ok(())
+}
+
+/// Write the node ids of every discovered test to `output` (or stdout), in a stable order
+fn collect_node_ids(
     args: SearchArgs,
-    skim_options: &SkimOptions,
-    state: &mut State,
-) -> eyre::Result<Option<String>> {
-    let SearchArgs {
-        root,
-        no_fuzzy_selection,
-    } = args;
-    let (files_tx, files_rx) = unbounded();
+    output: Option<PathBuf>,
+    shard: Option<Shard>,
+) -> eyre::Result<()> {
+    let progress = progress_spinner();
+    let files = find_files(&args, &progress)?;
+    if files.is_empty() {
+        progress.finish_and_clear();
+        eyre::bail!("No compatible test files found");
+    }
 
-    // check that some files were passed, otherwise default to the current working directory
-    let search_roots = if root.is_empty() {
-        let here = current_dir()?;
-        vec![here]
-    } else {
-        root
+    let rootdir = node_id_rootdir(&args)?;
+    let options = args.parse_options(rootdir.as_deref());
+    let mut tests: Vec<_> = collect_tests(files, &options, &progress)
+        .into_iter()
+        .collect();
+    progress.finish_and_clear();
+    sort_tests(&mut tests);
+    if let Some(shard) = shard {
+        tests.retain(|test| shard.contains(&test.output()));
+    }
+    truncate_tests(&mut tests, args.limit);
+
+    let mut writer: Box<dyn Write> = match &output {
+        Some(path) => Box::new(io::BufWriter::new(
+            fs::File::create(path)
+                .wrap_err_with(|| format!("creating output file {}", path.display()))?,
+        )),
+        None => Box::new(io::stdout().lock()),
     };
+    write_node_ids(&mut writer, &tests, args.null)?;
 
-    let mut file_handles = Vec::new();
-    for path in search_roots {
-        let span = tracing::debug_span!("", path = %path.display());
-        let _guard = span.enter();
-
-        tracing::debug!("listing files");
+    if let Some(path) = output {
+        tracing::debug!(n = tests.len(), path = %path.display(), "wrote node ids");
+    }
+    Ok(())
+}
 
-        let files_tx = files_tx.clone();
-        file_handles.push(thread::spawn(move || {
-            if let Err(e) = find_test_files(&path, files_tx) {
-                tracing::warn!(error = %e, path = %path.display(), "finding test files");
-            }
-        }));
+/// Print the node ids only in `old` as `-` lines and those only in `new` as `+` lines, in node id
+/// order, failing if any test was removed
+fn diff_collections(old: &Path, new: &Path, color: ColorMode) -> eyre::Result<ExitCode> {
+    let old_tests = collection(old)?;
+    let new_tests = collection(new)?;
+
+    let mut stdout = io::stdout().lock();
+    // unlike the fuzzy finder, this output is often piped or redirected
+    let use_colour = match color {
+        ColorMode::Auto => color.enabled() && stdout.is_terminal(),
+        _ => color.enabled(),
+    };
+    let mut n_removed = 0;
+    let mut n_added = 0;
+    // both sets are sorted, so the differences come out in node id order
+    for node_id in old_tests.symmetric_difference(&new_tests) {
+        let (sign, colour) = if old_tests.contains(node_id) {
+            n_removed += 1;
+            ('-', ANSI_RED)
+        } else {
+            n_added += 1;
+            ('+', ANSI_GREEN)
+        };
+        if use_colour {
+            writeln!(stdout, "{colour}{sign}{node_id}{ANSI_RESET}")?;
+        } else {
+            writeln!(stdout, "{sign}{node_id}")?;
+        }
     }
-    drop(files_tx);
 
-    for handle in file_handles {
-        let _ = handle.join();
+    if !is_quiet() {
+        eprintln!("{n_added} added, {n_removed} removed");
     }
+    Ok(if n_removed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
 
-    let files: Vec<_> = files_rx.into_iter().collect();
-    if files.is_empty() {
-        eyre::bail!("No compatible test files found");
+const ANSI_RED: &str = "\x1b[31m";
+const ANSI_GREEN: &str = "\x1b[32m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+/// The node ids of one side of a `diff`: the tests below `source`, relative to its rootdir, if
+/// it is a directory, otherwise the node ids listed in it
+fn collection(source: &Path) -> eyre::Result<BTreeSet<String>> {
+    if !source.is_dir() {
+        let node_ids = read_lines(source)
+            .wrap_err_with(|| format!("reading node ids from {}", source.display()))?;
+        return Ok(node_ids
+            .into_iter()
+            .map(|node_id| match node_id.strip_prefix("./") {
+                Some(node_id) => node_id.to_string(),
+                None => node_id,
+            })
+            .collect());
     }
 
-    tracing::debug!(n = files.len(), "finished collecting files");
+    let root =
+        std::path::absolute(source).wrap_err_with(|| format!("resolving {}", source.display()))?;
+    // node ids relative to each side's rootdir, so the same test in two checkouts compares
+    // equal, as do those written by `collect --relative-to-rootdir`
+    let rootdir = pytest_rootdir(&root)?;
+    let progress = progress_spinner();
+    let files = collect_test_paths(vec![root], None, &progress);
+    let options = ParseOptions {
+        rootdir: Some(&rootdir),
+        ..Default::default()
+    };
+    let node_ids = collect_tests(files, &options, &progress)
+        .into_iter()
+        .map(|test| test.output().into_owned())
+        .collect();
+    progress.finish_and_clear();
+    Ok(node_ids)
+}
 
-    let (test_tx, test_rx) = unbounded();
-    files
-        .into_par_iter()
-        .for_each_with(test_tx, |sender, path| {
-            if let Err(e) = parse_file(sender, &path) {
-                tracing::warn!(error = %e, path = %path.display(), "error parsing file");
-            }
+/// A subset of tests for splitting a test suite across CI nodes
+#[derive(Debug, Clone, Copy)]
+struct Shard {
+    /// 1-based index of this shard
+    index: u64,
+    count: u64,
+}
+
+impl Shard {
+    /// Whether the test with the given node id belongs to this shard
+    ///
+    /// Tests are assigned by a hash of their node id, so a test stays in the same shard when
+    /// other tests are added or removed.
+    fn contains(&self, node_id: &str) -> bool {
+        // FNV-1a, which unlike the std hasher is stable across Rust versions and platforms
+        let hash = node_id.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
         });
+        hash % self.count == self.index - 1
+    }
+}
 
-    if no_fuzzy_selection {
-        for test in test_rx {
-            println!("{}", test.text());
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (index, count) = s
+            .split_once('/')
+            .ok_or_else(|| format!("invalid shard {s}, expected i/n"))?;
+        let index: u64 = index
+            .parse()
+            .map_err(|_| format!("invalid shard index: {index}"))?;
+        let count: u64 = count
+            .parse()
+            .map_err(|_| format!("invalid shard count: {count}"))?;
+        if count == 0 || index == 0 || index > count {
+            return Err(format!("shard index must be between 1 and {count}"));
         }
-
-        return Ok(None); // No specific test selected in print mode
+        Ok(Self { index, count })
     }
+}
 
-    // perform fuzzy search
-    let search_result = skim::Skim::run_with(&skim_options, Some(test_rx))
-        .ok_or_else(|| eyre::eyre!("performing interactive search"))?;
+/// A line within a file, given on the command line as `file:line`
+#[derive(Debug, Clone)]
+struct Position {
+    file: PathBuf,
+    /// 1-based line number
+    line: usize,
+}
 
-    if search_result.is_abort {
-        tracing::info!("no tests selected");
-        return Ok(None);
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (file, line) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("invalid position {s}, expected file:line"))?;
+        let line: usize = line
+            .parse()
+            .map_err(|_| format!("invalid line number: {line}"))?;
+        if line == 0 {
+            return Err("line numbers start at 1".to_string());
+        }
+        Ok(Self {
+            file: PathBuf::from(file),
+            line,
+        })
     }
+}
 
-    let selected_items = search_result.selected_items;
+/// Print the node id of the test whose definition contains `position`
+fn locate_test(position: Position) -> eyre::Result<ExitCode> {
+    let tests = fs::read(&position.file)
+        .wrap_err("reading file")
+        .and_then(|source| parse_source(&source, &position.file))
+        .wrap_err_with(|| format!("parsing {}", position.file.display()))?;
+
+    let enclosing = tests
+        .iter()
+        .find(|test| test.lines.contains(&position.line));
+    match enclosing {
+        Some(test) => {
+            // the bare node id runs every parametrized case of the test
+            let node_id = test.output();
+            let node_id = match &test.parameter_id {
+                Some(parameter_id) => node_id
+                    .strip_suffix(&format!("[{parameter_id}]"))
+                    .unwrap_or(&node_id),
+                None => &node_id,
+            };
+            println!("{node_id}");
+            Ok(ExitCode::SUCCESS)
+        }
+        None => {
+            eprintln!(
+                "{}:{} is not inside a test",
+                position.file.display(),
+                position.line
+            );
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
 
-    if selected_items.is_empty() {
-        tracing::warn!("no tests selected");
-        return Ok(None);
+/// Print the node ids of the tests defined by the Python source on stdin, as if it were the
+/// contents of `filename`
+fn parse_stdin(filename: &Path) -> eyre::Result<ExitCode> {
+    let mut source = Vec::new();
+    io::stdin()
+        .read_to_end(&mut source)
+        .wrap_err("reading source from stdin")?;
+
+    let tests = parse_source(&source, filename)
+        .wrap_err_with(|| format!("parsing {}", filename.display()))?;
+    let mut stdout = io::stdout().lock();
+    for test in &tests {
+        writeln!(stdout, "{}", test.output())?;
     }
+    Ok(ExitCode::SUCCESS)
+}
 
-    if selected_items.len() > 1 {
-        panic!("programming error: multiple tests selected");
+/// Keep only the first `limit` tests, noting on stderr if any were dropped
+fn truncate_tests(tests: &mut Vec<Arc<dyn SkimItem>>, limit: Option<usize>) {
+    if let Some(limit) = limit
+        && tests.len() > limit
+    {
+        eprintln!("showing the first {limit} of {} tests", tests.len());
+        tests.truncate(limit);
     }
+}
 
-    let test = selected_items[0].text();
-    state.set_last_test(test.clone())?;
-    println!("{test}");
+/// Sort tests by file, then class, then name, then parameter id
+fn sort_tests(tests: &mut [Arc<dyn SkimItem>]) {
+    tests.sort_by_cached_key(|test| match test.as_any().downcast_ref::<TestCase>() {
+        Some(test_case) => (
+            test_case.file.clone(),
+            test_case.classes.clone(),
+            test_case.name.clone(),
+            test_case.parameter_id.clone(),
+        ),
+        None => (
+            PathBuf::from(test.output().as_ref()),
+            Vec::new(),
+            String::new(),
+            None,
+        ),
+    });
+}
 
-    Ok(Some(test.to_string()))
+/// Re-send all tests from `test_rx` in sorted order
+///
+/// If `limit` is given, only the first `limit` tests in sorted order are sent.
+fn sorted(
+    test_rx: Receiver<Arc<dyn SkimItem>>,
+    limit: Option<usize>,
+) -> Receiver<Arc<dyn SkimItem>> {
+    let mut tests: Vec<_> = test_rx.into_iter().collect();
+    sort_tests(&mut tests);
+    truncate_tests(&mut tests, limit);
+    resend(tests)
 }
 
-fn perform_grep_search(
-    pattern: String,
-    args: SearchArgs,
-    run_command: Option<String>,
-) -> eyre::Result<()> {
-    // Compile the regex pattern
-    let regex =
-        Regex::new(&pattern).wrap_err_with(|| format!("compiling regex pattern: {}", pattern))?;
+/// Re-send all tests from `test_rx` with those in `history` first, most recently run first
+///
+/// The remaining tests keep their order.
+fn history_first(
+    test_rx: Receiver<Arc<dyn SkimItem>>,
+    history: &[String],
+) -> Receiver<Arc<dyn SkimItem>> {
+    let recency: HashMap<&str, usize> = history
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(recency, node_id)| (node_id.as_str(), recency))
+        .collect();
+
+    let mut tests: Vec<_> = test_rx.into_iter().collect();
+    tests.sort_by_cached_key(|test| {
+        recency
+            .get(test.output().as_ref())
+            .copied()
+            .unwrap_or(usize::MAX)
+    });
+    resend(tests)
+}
 
-    let (files_tx, files_rx) = unbounded();
+/// Send `tests` through a new channel, in order
+fn resend(tests: Vec<Arc<dyn SkimItem>>) -> Receiver<Arc<dyn SkimItem>> {
+    let (test_tx, test_rx) = unbounded();
+    for test in tests {
+        let _ = test_tx.send(test);
+    }
+    test_rx
+}
 
-    // Use provided roots or default to current working directory
-    let search_roots = if args.root.is_empty() {
-        let here = current_dir()?;
-        vec![here]
-    } else {
-        args.root
-    };
+/// Fuzzy finder settings shared by every search
+struct Finder {
+    /// skim colour scheme
+    colour: Option<&'static str>,
+    /// Prompt shown before the query
+    prompt: String,
+    /// Header replacing the default summary line
+    header: Option<String>,
+}
 
-    let mut file_handles = Vec::new();
-    for path in search_roots {
-        let span = tracing::debug_span!("", path = %path.display());
-        let _guard = span.enter();
+impl Finder {
+    /// Build the options for a search, showing `header` unless the user gave their own
+    fn options<'a>(&'a self, header: &'a str) -> SkimOptions<'a> {
+        SkimOptionsBuilder::default()
+            .multi(false)
+            .color(self.colour)
+            .prompt(Some(&self.prompt))
+            .header(Some(self.header.as_deref().unwrap_or(header)))
+            // accept with ctrl-e as well as enter, so the selected test can be opened in the editor
+            .expect(Some("ctrl-e".to_string()))
+            .build()
+            .expect("invalid skim options")
+    }
+}
 
-        tracing::debug!("listing files");
+/// Describe where a search looks for tests, for the fuzzy finder header
+fn search_location(args: &SearchArgs) -> eyre::Result<String> {
+    if let Some(source) = &args.files_from {
+        return Ok(format!("files listed in {}", source.display()));
+    }
 
-        let files_tx = files_tx.clone();
-        file_handles.push(thread::spawn(move || {
-            if let Err(e) = find_test_files(&path, files_tx) {
-                tracing::warn!(error = %e, path = %path.display(), "finding test files");
-            }
-        }));
+    let roots = search_roots(args.root.clone())?;
+    Ok(roots
+        .iter()
+        .map(|root| root.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", "))
+}
+
+/// How long a detected colour scheme is reused before detecting it again
+const COLOUR_SCHEME_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// The system colour scheme, as detected by `dark_light`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ColourScheme {
+    Dark,
+    Light,
+    /// The system does not prefer either
+    Unspecified,
+}
+
+impl ColourScheme {
+    fn detect() -> Self {
+        match dark_light::detect().unwrap_or(dark_light::Mode::Dark) {
+            dark_light::Mode::Dark => ColourScheme::Dark,
+            dark_light::Mode::Light => ColourScheme::Light,
+            _ => ColourScheme::Unspecified,
+        }
     }
-    drop(files_tx);
 
-    for handle in file_handles {
-        let _ = handle.join();
+    /// The skim colour theme for this scheme, or `None` for skim's default
+    fn skim_theme(self) -> Option<&'static str> {
+        match self {
+            ColourScheme::Dark => Some("dark"),
+            ColourScheme::Light => Some("light"),
+            ColourScheme::Unspecified => None,
+        }
     }
+}
 
-    let files: Vec<_> = files_rx.into_iter().collect();
-    if files.is_empty() {
-        eyre::bail!("No compatible test files found");
+/// A colour scheme cached in the persisted state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DetectedColourScheme {
+    scheme: ColourScheme,
+    detected_at: SystemTime,
+}
+
+/// The skim colour theme to use
+///
+/// Detecting the system colour scheme can be slow, as it may ask the desktop environment, so the
+/// result is cached for [`COLOUR_SCHEME_TTL`]. Without colour nothing is detected.
+fn get_colour(color: ColorMode, state: &mut State) -> eyre::Result<Option<&'static str>> {
+    if !color.enabled() {
+        return Ok(Some("bw"));
     }
 
-    tracing::debug!(n = files.len(), "finished collecting files");
+    if let Some(cached) = &state.persisted.colour_scheme
+        && cached
+            .detected_at
+            .elapsed()
+            .is_ok_and(|age| age < COLOUR_SCHEME_TTL)
+    {
+        return Ok(cached.scheme.skim_theme());
+    }
 
-    let (test_tx, test_rx) = unbounded();
-    files
-        .into_par_iter()
-        .for_each_with(test_tx, |sender, path| {
-            if let Err(e) = parse_file_with_regex(sender, &path, Some(&regex)) {
-                tracing::warn!(error = %e, path = %path.display(), "error parsing file for grep");
-            }
+    let scheme = ColourScheme::detect();
+    // detecting again next time is all that is lost if this fails
+    if let Err(e) = state.update(|persisted| {
+        persisted.colour_scheme = Some(DetectedColourScheme {
+            scheme,
+            detected_at: SystemTime::now(),
         });
+        Ok(())
+    }) {
+        tracing::debug!(error = %e, "caching detected colour scheme");
+    }
+    Ok(scheme.skim_theme())
+}
+
+fn run_repl(
+    state: &mut State,
+    finder: Finder,
+    color: ColorMode,
+    command_template: String,
+    unittest_names: bool,
+    remote: Option<Remote>,
+    confirm: bool,
+) -> eyre::Result<ExitCode> {
+    status!("🔍 testsearch REPL mode\n");
+    status!("Command template: {}\n", command_template);
+    status!(
+        "Press 'f' to find and execute test, 'e' to edit command before execution, 'r' to rerun last test, 'ctrl-c', 'q', or 'esc' to exit\n"
+    );
+    status!("\n");
+
+    let _raw_mode = RawModeGuard::enable()?;
+    repl_loop(
+        state,
+        &finder,
+        color,
+        &command_template,
+        unittest_names,
+        remote.as_ref(),
+        confirm,
+    )
+}
 
-    let matching_tests: Vec<_> = test_rx.into_iter().collect();
+/// Keeps the terminal in raw mode while alive, leaving it when dropped, so the terminal is
+/// restored however the REPL ends, including errors and panics
+struct RawModeGuard;
 
-    if matching_tests.is_empty() {
-        println!("No tests found matching pattern: {}", pattern);
-        return Ok(());
+impl RawModeGuard {
+    fn enable() -> eyre::Result<Self> {
+        enable_raw_mode().context("enabling raw terminal mode")?;
+        Ok(Self)
     }
+}
 
-    // Print all matching test node IDs
-    for test in &matching_tests {
-        println!("{}", test.text());
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
     }
+}
 
-    // If run command is provided, execute the tests
-    if let Some(command_template) = run_command {
-        println!("\nExecuting matching tests...\n");
+/// Restore the terminal before a panic is reported, so the report is not printed in raw mode
+///
+/// `RawModeGuard` only runs while unwinding, which is after the report has been printed.
+fn install_panic_hook() {
+    let report = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        report(info);
+    }));
+}
 
-        for test in matching_tests {
-            let test_path = test.text();
-            if let Err(e) = execute_test_command(&command_template, &test_path) {
-                eprintln!("❌ Execution failed for {}: {}", test_path, e);
-            }
+/// Files which configure pytest, in the order pytest looks for them, and the section that must
+/// be present in them (if any)
+const PYTEST_CONFIG_FILES: &[(&str, Option<&str>)] = &[
+    ("pytest.ini", None),
+    (".pytest.ini", None),
+    ("pyproject.toml", Some("[tool.pytest")),
+    ("tox.ini", Some("[pytest]")),
+    ("setup.cfg", Some("[tool:pytest]")),
+];
+
+/// Whether `dir` has a file configuring pytest, as listed in `PYTEST_CONFIG_FILES`
+fn has_pytest_config(dir: &Path) -> bool {
+    PYTEST_CONFIG_FILES.iter().any(|(name, section)| {
+        let path = dir.join(name);
+        match section {
+            None => path.is_file(),
+            Some(section) => fs::read_to_string(&path)
+                .map(|contents| contents.contains(section))
+                .unwrap_or_default(),
         }
-    }
+    })
+}
 
-    Ok(())
+/// Files which mark the root of a Python project
+const PROJECT_ROOT_FILES: &[&str] = &["pyproject.toml", "setup.py", "setup.cfg", "uv.lock", ".git"];
+
+/// Infer a command template to run tests with, from the project around `root`
+///
+/// pytest is used if it is configured, otherwise `python -m unittest` if `unittest_only`
+/// reports that every test is a unittest test, falling back to pytest. The command is run
+/// through `uv run` if the project has a `uv.lock`.
+///
+/// Also returns whether the command runs unittest, which needs dotted test names rather than
+/// pytest node ids.
+fn default_run_command(root: &Path, unittest_only: impl FnOnce() -> bool) -> (String, bool) {
+    let project_root = project_root(root);
+    tracing::debug!(root = %project_root.display(), "inferring run command");
+
+    let pytest_configured =
+        has_pytest_config(&project_root) || project_root.join("conftest.py").is_file();
+
+    let unittest = !pytest_configured && unittest_only();
+    let command = if unittest {
+        "python -m unittest {}"
+    } else {
+        "pytest {}"
+    };
+
+    let command = if project_root.join("uv.lock").is_file() {
+        format!("uv run {command}")
+    } else {
+        command.to_string()
+    };
+    (command, unittest)
 }
 
-fn get_colour() -> eyre::Result<Option<&'static str>> {
-    use dark_light::Mode::*;
-    match dark_light::detect().unwrap_or(Dark) {
-        Dark => Ok(Some("dark")),
-        Light => Ok(Some("light")),
-        _ => Ok(None),
-    }
+/// Find the directory pytest would use as its rootdir when run on `start`, or `start` itself if
+/// there is none
+///
+/// pytest prefers the closest directory with a config file (see [`has_pytest_config`]), then one
+/// with a `setup.py`, and only then one with a `pyproject.toml` that does not configure it.
+fn pytest_rootdir(start: &Path) -> eyre::Result<PathBuf> {
+    let start =
+        std::path::absolute(start).wrap_err_with(|| format!("resolving {}", start.display()))?;
+    let rootdir = start
+        .ancestors()
+        .find(|dir| has_pytest_config(dir))
+        .or_else(|| start.ancestors().find(|dir| dir.join("setup.py").is_file()))
+        .or_else(|| {
+            start
+                .ancestors()
+                .find(|dir| dir.join("pyproject.toml").is_file())
+        })
+        .unwrap_or(&start);
+    Ok(rootdir.to_path_buf())
 }
 
-fn run_repl(
-    mut state: State,
-    skim_options: SkimOptions,
-    command_template: String,
-) -> eyre::Result<ExitCode> {
-    println!("🔍 testsearch REPL mode");
-    println!("Command template: {}", command_template);
-    println!(
-        "Press 'f' to find and execute test, 'e' to edit command before execution, 'r' to rerun last test, 'ctrl-c', 'q', or 'esc' to exit"
-    );
-    println!();
+/// Drop the files outside `rootdir` whose node ids would be the same as those of a file inside it
+///
+/// Files outside the rootdir keep the path they were found by in their node ids, which can be the
+/// rootdir-relative path of a different file, e.g. `tests/test_foo.py` below a second search root
+/// in a working directory outside the rootdir. Selecting one of those node ids later would run
+/// the wrong test, so the file outside the rootdir, which pytest would not find by that id, is
+/// skipped.
+fn skip_colliding_node_ids(
+    files: Vec<PathBuf>,
+    rootdir: &Path,
+    progress: &ProgressBar,
+) -> Vec<PathBuf> {
+    let relative: HashSet<PathBuf> = files
+        .iter()
+        .filter_map(|file| rootdir_relative(file, rootdir))
+        .collect();
+    files
+        .into_iter()
+        .filter(|file| {
+            let collides =
+                rootdir_relative(file, rootdir).is_none() && relative.contains(file.as_path());
+            if collides {
+                progress.suspend(|| {
+                    eprintln!(
+                        "warning: skipping {}, its node ids would be the same as those of {}; \
+                         search without --relative-to-rootdir to include it",
+                        file.display(),
+                        rootdir.join(file).display()
+                    )
+                });
+            }
+            !collides
+        })
+        .collect()
+}
 
-    enable_raw_mode().context("enabling raw terminal mode")?;
+/// The rootdir node ids should be relative to, if requested, based on the first search root
+fn node_id_rootdir(args: &SearchArgs) -> eyre::Result<Option<PathBuf>> {
+    if !args.relative_to_rootdir {
+        return Ok(None);
+    }
 
-    let result = repl_loop(&mut state, &skim_options, &command_template);
+    let start = match args.root.first() {
+        Some(root) if args.files_from.is_none() => root.clone(),
+        _ => current_dir()?,
+    };
+    let rootdir = pytest_rootdir(&start)?;
+    tracing::debug!(rootdir = %rootdir.display(), "making node ids relative to rootdir");
+    Ok(Some(rootdir))
+}
 
-    // Always ensure we disable raw mode, even on error
-    let _ = disable_raw_mode();
+/// Find the closest directory at or above `start` which looks like a project root, or `start`
+/// itself if there is none
+fn project_root(start: &Path) -> PathBuf {
+    let start = start.canonicalize().unwrap_or_else(|_| start.to_path_buf());
+    start
+        .ancestors()
+        .find(|dir| {
+            PROJECT_ROOT_FILES
+                .iter()
+                .any(|name| dir.join(name).exists())
+        })
+        .unwrap_or(&start)
+        .to_path_buf()
+}
 
-    result
+/// Whether every test is a unittest test, and there is at least one
+fn unittest_only(tests: &[Arc<dyn SkimItem>]) -> bool {
+    !tests.is_empty()
+        && tests.iter().all(|test| {
+            test.as_any()
+                .downcast_ref::<TestCase>()
+                .is_some_and(|test_case| test_case.framework == Framework::Unittest)
+        })
 }
 
-fn execute_test_command(command_template: &str, test_path: &str) -> eyre::Result<()> {
-    // Validate that the command template contains the placeholder
-    if !command_template.contains("{}") {
-        eyre::bail!("Command template must contain '{{}}' placeholder for test path");
-    }
+/// Convert a pytest node id such as `tests/test_foo.py::FooTests::test_bar` into the dotted name
+/// unittest expects, e.g. `tests.test_foo.FooTests.test_bar`
+fn unittest_name(node_id: &TestId) -> String {
+    std::iter::once(unittest_module(&node_id.file))
+        .chain(node_id.classes.iter().cloned())
+        .chain(node_id.item_name().map(Cow::into_owned))
+        .collect::<Vec<_>>()
+        .join(".")
+}
 
-    // Replace the placeholder with the actual test path
-    let command = command_template.replace("{}", test_path);
+/// The dotted module name unittest expects for `file`, e.g. `tests.test_foo`
+fn unittest_module(file: &Path) -> String {
+    // unittest resolves modules relative to the working directory
+    let file = current_dir()
+        .ok()
+        .and_then(|here| file.strip_prefix(here).ok())
+        .unwrap_or(file);
+    file.with_extension("")
+        .components()
+        .filter_map(|component| match component {
+            std::path::Component::Normal(part) => part.to_str(),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
 
-    print!("Executing: {}\r\n", command);
-    io::stdout().flush()?;
+/// Run the test `node_id` with `command_template`, streaming its output, and return its exit
+/// status
+///
+/// If `log_file` is given, the output is also written to it.
+fn execute_test_command(
+    command_template: &str,
+    unittest_names: bool,
+    quote_ids: bool,
+    node_id: &TestId,
+    log_file: Option<&Path>,
+) -> eyre::Result<ExitStatus> {
+    let command = test_command(command_template, unittest_names, quote_ids, node_id)?;
+    announce_command(&command)?;
 
     // Parse the command into program and arguments
     let parts: Vec<&str> = command.split_whitespace().collect();
@@ -486,7 +3574,8 @@ fn execute_test_command(command_template: &str, test_path: &str) -> eyre::Result
     let program = parts[0];
     let args = &parts[1..];
 
-    // Start the process with piped I/O for real-time output
+    // Start the process with piped I/O for real-time output. The environment is inherited, so
+    // pytest still honours `PYTEST_ADDOPTS`
     let mut child = std::process::Command::new(program)
         .args(args)
         .env("FORCE_COLOR", "1")
@@ -512,6 +3601,17 @@ fn execute_test_command(command_template: &str, test_path: &str) -> eyre::Result
     let stdout_reader = BufReader::new(stdout);
     let stderr_reader = BufReader::new(stderr);
 
+    // both streams are written to the same log, a line at a time
+    let log = log_file
+        .map(|log_file| {
+            fs::File::create(log_file)
+                .wrap_err_with(|| format!("creating log file {}", log_file.display()))
+        })
+        .transpose()?
+        .map(|log| Arc::new(Mutex::new(log)));
+    let stdout_log = log.clone();
+    let stderr_log = log;
+
     // Stream stdout in real-time
     let stdout_handle = thread::spawn(move || {
         for line in stdout_reader.lines() {
@@ -519,6 +3619,7 @@ fn execute_test_command(command_template: &str, test_path: &str) -> eyre::Result
                 Ok(line) => {
                     print!("{}\r\n", line);
                     let _ = io::stdout().flush();
+                    write_log_line(stdout_log.as_deref(), &line);
                 }
                 Err(_) => break,
             }
@@ -532,37 +3633,152 @@ fn execute_test_command(command_template: &str, test_path: &str) -> eyre::Result
                 Ok(line) => {
                     print!("{}\r\n", line);
                     let _ = io::stdout().flush();
+                    write_log_line(stderr_log.as_deref(), &line);
                 }
                 Err(_) => break,
             }
         }
     });
 
-    // Wait for the process to complete
-    let status = child
-        .wait()
-        .context("waiting for test command to complete")?;
+    let status = wait_for_test_command(&mut child)?;
 
     // Wait for output threads to finish
     let _ = stdout_handle.join();
     let _ = stderr_handle.join();
 
-    if status.success() {
-        print!("✅ Test execution completed successfully\r\n");
-    } else {
-        print!(
-            "❌ Test execution failed (exit code: {})\r\n",
-            status.code().unwrap_or(-1)
-        );
+    if status.success() {
+        status!("✅ Test execution completed successfully\r\n");
+    } else {
+        status!(
+            "❌ Test execution failed (exit code: {})\r\n",
+            status.code().unwrap_or(-1)
+        );
+    }
+
+    io::stdout().flush()?;
+    Ok(status)
+}
+
+/// The command which runs the test `node_id`, from `command_template`
+///
+/// With `unittest_names`, the test is given as the dotted name unittest expects instead of its
+/// node id. This is only set for the inferred `python -m unittest` template, so a template the
+/// user wrote always gets node ids. With `quote_ids`, as for `--remote`, the test is quoted for
+/// the shell it runs in.
+fn test_command(
+    command_template: &str,
+    unittest_names: bool,
+    quote_ids: bool,
+    node_id: &TestId,
+) -> eyre::Result<String> {
+    // Validate that the command template contains the placeholder
+    if !command_template.contains("{}") {
+        eyre::bail!("Command template must contain '{{}}' placeholder for test path");
+    }
+
+    let test_path = if unittest_names {
+        unittest_name(node_id)
+    } else {
+        node_id.to_string()
+    };
+    let test_path = if quote_ids {
+        shell_quote(&test_path)
+    } else {
+        test_path
+    };
+
+    // Replace the placeholder with the actual test path
+    Ok(command_template.replace("{}", &test_path))
+}
+
+/// With `--confirm`, show the command which would run `node_id` and ask whether to run it
+///
+/// A single key answers, like the REPL's own commands, and anything but `y` declines.
+fn confirm_test_command(
+    confirm: bool,
+    command_template: &str,
+    unittest_names: bool,
+    quote_ids: bool,
+    node_id: &TestId,
+) -> eyre::Result<bool> {
+    if !confirm {
+        return Ok(true);
+    }
+    let command = test_command(command_template, unittest_names, quote_ids, node_id)?;
+    print!("Run {command}? [y/N] ");
+    io::stdout().flush()?;
+
+    let confirmed = {
+        let _raw_mode = RawModeGuard::enable()?;
+        loop {
+            if let Event::Key(KeyEvent { code, .. }) =
+                event::read().context("reading terminal event")?
+            {
+                break matches!(code, KeyCode::Char('y' | 'Y'));
+            }
+        }
+    };
+    print!("{}\r\n", if confirmed { "y" } else { "n" });
+    Ok(confirmed)
+}
+
+/// Wait for a test command to exit, killing it if it is still running `INTERRUPT_GRACE_PERIOD`
+/// after Ctrl-C was pressed
+///
+/// The command gets the terminal's interrupt too, so it normally exits by itself first.
+fn wait_for_test_command(child: &mut std::process::Child) -> eyre::Result<ExitStatus> {
+    let mut interrupted_at = None;
+    loop {
+        if let Some(status) = child
+            .try_wait()
+            .context("waiting for test command to complete")?
+        {
+            return Ok(status);
+        }
+        if is_interrupted() {
+            let interrupted_at = *interrupted_at.get_or_insert_with(Instant::now);
+            if interrupted_at.elapsed() >= INTERRUPT_GRACE_PERIOD {
+                tracing::debug!(pid = child.id(), "killing interrupted test command");
+                let _ = child.kill();
+                return child
+                    .wait()
+                    .context("waiting for interrupted test command to exit");
+            }
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Append `line` to a test's log file, if there is one
+///
+/// Errors are ignored so a full disk does not interrupt the test run.
+fn write_log_line(log: Option<&Mutex<fs::File>>, line: &str) {
+    if let Some(log) = log
+        && let Ok(mut log) = log.lock()
+    {
+        let _ = writeln!(log, "{line}");
     }
+}
 
+/// Print the command about to be run, along with any options pytest will add from the environment
+fn announce_command(command: &str) -> eyre::Result<()> {
+    status!("Executing: {}\r\n", command);
+    if let Ok(addopts) = std::env::var("PYTEST_ADDOPTS")
+        && !addopts.is_empty()
+    {
+        status!("With PYTEST_ADDOPTS: {}\r\n", addopts);
+    }
     io::stdout().flush()?;
     Ok(())
 }
 
-fn edit_command_for_test(command_template: &str, test_path: &str) -> eyre::Result<String> {
+fn edit_command_for_test(
+    command_template: &str,
+    quote_ids: bool,
+    node_id: &TestId,
+) -> eyre::Result<String> {
     // Create the default command by filling in the template
-    let default_command = command_template.replace("{}", test_path);
+    let default_command = test_command(command_template, false, quote_ids, node_id)?;
 
     // Create a rustyline editor
     let mut rl = DefaultEditor::new().context("creating rustyline editor")?;
@@ -597,8 +3813,7 @@ fn edit_command_for_test(command_template: &str, test_path: &str) -> eyre::Resul
 }
 
 fn execute_raw_command(command: &str) -> eyre::Result<()> {
-    print!("Executing: {}\r\n", command);
-    io::stdout().flush()?;
+    announce_command(command)?;
 
     // Parse the command into program and arguments
     let parts: Vec<&str> = command.split_whitespace().collect();
@@ -609,7 +3824,8 @@ fn execute_raw_command(command: &str) -> eyre::Result<()> {
     let program = parts[0];
     let args = &parts[1..];
 
-    // Start the process with piped I/O for real-time output
+    // Start the process with piped I/O for real-time output. The environment is inherited, so
+    // pytest still honours `PYTEST_ADDOPTS`
     let mut child = std::process::Command::new(program)
         .args(args)
         .env("FORCE_COLOR", "1")
@@ -671,9 +3887,9 @@ fn execute_raw_command(command: &str) -> eyre::Result<()> {
     let _ = stderr_handle.join();
 
     if status.success() {
-        print!("✅ Test execution completed successfully\r\n");
+        status!("✅ Test execution completed successfully\r\n");
     } else {
-        print!(
+        status!(
             "❌ Test execution failed (exit code: {})\r\n",
             status.code().unwrap_or(-1)
         );
@@ -685,10 +3901,14 @@ fn execute_raw_command(command: &str) -> eyre::Result<()> {
 
 fn repl_loop(
     state: &mut State,
-    skim_options: &SkimOptions,
+    finder: &Finder,
+    color: ColorMode,
     command_template: &str,
+    unittest_names: bool,
+    remote: Option<&Remote>,
+    confirm: bool,
 ) -> eyre::Result<ExitCode> {
-    let mut last_executed_test: Option<String> = None;
+    let mut last_executed_test: Option<TestId> = None;
     loop {
         print!("testsearch> ");
         io::stdout().flush().context("flushing stdout")?;
@@ -700,30 +3920,56 @@ fn repl_loop(
                 ..
             }) => {
                 print!("f\r\n");
-                print!("🔍 Finding and executing test...\r\n");
+                status!("🔍 Finding and executing test...\r\n");
                 io::stdout().flush()?;
 
                 // Temporarily disable raw mode for skim
                 disable_raw_mode().context("disabling raw mode for search")?;
-                let search_result = perform_search(SearchArgs::default(), skim_options, state);
+                let search_result = perform_search(
+                    SearchArgs::default().with_env_defaults(),
+                    finder,
+                    color,
+                    state,
+                );
 
                 match search_result {
-                    Ok(Some(selected_test)) => {
-                        print!("Selected test: {}\r\n", selected_test);
-
-                        // Execute the test
-                        match execute_test_command(command_template, &selected_test) {
-                            Err(e) => {
-                                print!("❌ Execution failed: {}\r\n", e);
-                            }
-                            _ => {
-                                // Store the last executed test for rerun
-                                last_executed_test = Some(selected_test);
+                    Ok(SearchOutcome::Selected(selected_test)) => {
+                        status!("Selected test: {}\r\n", selected_test);
+
+                        // Execute the test, once confirmed if asked to
+                        let test = remote_test_id(&selected_test, remote);
+                        match confirm_test_command(
+                            confirm,
+                            command_template,
+                            unittest_names,
+                            remote.is_some(),
+                            &test,
+                        ) {
+                            Ok(true) => {
+                                let start = Instant::now();
+                                match execute_test_command(
+                                    command_template,
+                                    unittest_names,
+                                    remote.is_some(),
+                                    &test,
+                                    None,
+                                ) {
+                                    Err(e) => {
+                                        print!("❌ Execution failed: {}\r\n", e);
+                                    }
+                                    _ => {
+                                        record_duration(state, &selected_test, start.elapsed());
+                                        // Store the last executed test for rerun
+                                        last_executed_test = Some(selected_test);
+                                    }
+                                }
                             }
+                            Ok(false) => status!("Skipped {selected_test}\r\n"),
+                            Err(e) => print!("❌ Execution failed: {}\r\n", e),
                         }
                     }
-                    Ok(None) => {
-                        print!("❌ No test was selected\r\n");
+                    Ok(_) => {
+                        status!("❌ No test was selected\r\n");
                     }
                     Err(e) => {
                         print!("❌ Search failed: {}\r\n", e);
@@ -738,19 +3984,28 @@ fn repl_loop(
                 ..
             }) => {
                 print!("e\r\n");
-                print!("✏️ Finding test and editing command...\r\n");
+                status!("✏️ Finding test and editing command...\r\n");
                 io::stdout().flush()?;
 
                 // Temporarily disable raw mode for skim
                 disable_raw_mode().context("disabling raw mode for search")?;
-                let search_result = perform_search(SearchArgs::default(), skim_options, state);
+                let search_result = perform_search(
+                    SearchArgs::default().with_env_defaults(),
+                    finder,
+                    color,
+                    state,
+                );
 
                 match search_result {
-                    Ok(Some(selected_test)) => {
-                        print!("Selected test: {}\r\n", selected_test);
+                    Ok(SearchOutcome::Selected(selected_test)) => {
+                        status!("Selected test: {}\r\n", selected_test);
 
                         // Edit the command for this test
-                        match edit_command_for_test(command_template, &selected_test) {
+                        match edit_command_for_test(
+                            command_template,
+                            remote.is_some(),
+                            &remote_test_id(&selected_test, remote),
+                        ) {
                             Ok(edited_command) => {
                                 print!("Edited command: {}\r\n", edited_command);
 
@@ -770,8 +4025,8 @@ fn repl_loop(
                             }
                         }
                     }
-                    Ok(None) => {
-                        print!("❌ No test was selected\r\n");
+                    Ok(_) => {
+                        status!("❌ No test was selected\r\n");
                     }
                     Err(e) => {
                         print!("❌ Search failed: {}\r\n", e);
@@ -790,7 +4045,7 @@ fn repl_loop(
                 ..
             }) => {
                 print!("r\r\n");
-                print!("🔄 Rerunning last test...\r\n");
+                status!("🔄 Rerunning last test...\r\n");
                 io::stdout().flush()?;
 
                 match &last_executed_test {
@@ -798,9 +4053,30 @@ fn repl_loop(
                         // Temporarily disable raw mode for test execution
                         disable_raw_mode().context("disabling raw mode for rerun")?;
 
-                        print!("Rerunning: {}\r\n", test_path);
-                        if let Err(e) = execute_test_command(command_template, test_path) {
-                            print!("❌ Rerun failed: {}\r\n", e);
+                        status!("Rerunning: {}\r\n", test_path);
+                        let test = remote_test_id(test_path, remote);
+                        match confirm_test_command(
+                            confirm,
+                            command_template,
+                            unittest_names,
+                            remote.is_some(),
+                            &test,
+                        ) {
+                            Ok(true) => {
+                                let start = Instant::now();
+                                match execute_test_command(
+                                    command_template,
+                                    unittest_names,
+                                    remote.is_some(),
+                                    &test,
+                                    None,
+                                ) {
+                                    Ok(_) => record_duration(state, test_path, start.elapsed()),
+                                    Err(e) => print!("❌ Rerun failed: {}\r\n", e),
+                                }
+                            }
+                            Ok(false) => status!("Skipped {test_path}\r\n"),
+                            Err(e) => print!("❌ Rerun failed: {}\r\n", e),
                         }
 
                         enable_raw_mode().context("re-enabling raw mode after rerun")?;
@@ -825,7 +4101,7 @@ fn repl_loop(
                 ..
             }) => {
                 print!("\r\n");
-                print!("👋 Goodbye!\r\n");
+                status!("👋 Goodbye!\r\n");
                 return Ok(ExitCode::SUCCESS);
             }
             Event::Key(KeyEvent {
@@ -848,11 +4124,45 @@ fn repl_loop(
     }
 }
 
+/// Record how long a test run from the repl took, reporting rather than failing on errors so
+/// the repl keeps going
+fn record_duration(state: &mut State, test: &TestId, duration: Duration) {
+    if let Err(e) = state.record_durations([(test.to_string(), duration)]) {
+        print!("⚠️ Could not record test duration: {}\r\n", e);
+    }
+}
+
+/// Which directories' history `rerun` picks from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HistoryScope {
+    /// The history of a single directory
+    Directory,
+    /// The history of every directory
+    Global,
+    /// The tests pinned in a single directory
+    Pinned,
+}
+
+impl HistoryScope {
+    fn toggled(self) -> Self {
+        match self {
+            HistoryScope::Directory => HistoryScope::Global,
+            HistoryScope::Global => HistoryScope::Directory,
+            // pinned tests are only picked from when asked for
+            HistoryScope::Pinned => HistoryScope::Pinned,
+        }
+    }
+}
+
+/// Key which switches `rerun` between the directory and global history
+const TOGGLE_SCOPE_KEY: &str = "ctrl-g";
+
 fn rerun_test(
     root: Option<PathBuf>,
     last: bool,
+    mut scope: HistoryScope,
     state: &State,
-    skim_options: &SkimOptions,
+    finder: &Finder,
 ) -> eyre::Result<ExitCode> {
     // fetch the tests from the state using root as the key
     let search_root = if let Some(root) = root {
@@ -861,385 +4171,639 @@ fn rerun_test(
         current_dir()?
     };
 
-    let history = state.persisted.history(search_root.clone());
-    match history {
-        Some(history) => {
-            if last {
-                // pick last test from history
-                match history.last() {
-                    Some(last_test) => {
-                        println!("{}", last_test);
-                        Ok(ExitCode::SUCCESS)
-                    }
-                    None => {
-                        eyre::bail!("No test history found for path {}", search_root.display())
-                    }
-                }
-            } else {
-                // perform fuzzy search through history
-                let (test_tx, test_rx) = unbounded();
-                for test in history {
-                    let item: Arc<dyn SkimItem> = Arc::new(TestHistoryEntry { text: test });
-                    test_tx.send(item)?;
-                }
+    let entries = history_entries(state, &search_root, scope);
+    if entries.is_empty() {
+        match scope {
+            HistoryScope::Global => eyre::bail!("No test history found"),
+            HistoryScope::Directory => {
+                eyre::bail!("No test history found for path {}", search_root.display())
+            }
+            HistoryScope::Pinned => {
+                eyre::bail!("No pinned tests found for path {}", search_root.display())
+            }
+        }
+    }
 
-                let search_result = skim::Skim::run_with(skim_options, Some(test_rx))
-                    .ok_or_else(|| eyre::eyre!("performing interactive search"))?;
+    if last {
+        // pick last test from history
+        if let Some(last_test) = entries.last() {
+            println!("{}", complete_node_id(last_test));
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
 
-                if search_result.is_abort {
-                    tracing::info!("no tests selected");
-                    return Ok(ExitCode::SUCCESS);
-                }
+    // skim cannot swap its item source while running, so switching scope restarts the search
+    // with the other history, keeping the query
+    let mut entries = Some(entries);
+    let mut query = String::new();
+    let search_result = loop {
+        let entries = entries
+            .take()
+            .unwrap_or_else(|| history_entries(state, &search_root, scope));
+
+        let header = match scope {
+            HistoryScope::Directory => format!(
+                "history for {} ({TOGGLE_SCOPE_KEY}: all directories)",
+                search_root.display()
+            ),
+            HistoryScope::Global => {
+                format!("history for all directories ({TOGGLE_SCOPE_KEY}: this directory)")
+            }
+            HistoryScope::Pinned => format!("pinned tests for {}", search_root.display()),
+        };
+        let skim_options = SkimOptionsBuilder::default()
+            .multi(false)
+            .color(finder.colour)
+            .prompt(Some(&finder.prompt))
+            .header(Some(finder.header.as_deref().unwrap_or(&header)))
+            .query(Some(&query))
+            .expect((scope != HistoryScope::Pinned).then(|| TOGGLE_SCOPE_KEY.to_string()))
+            .build()
+            .expect("invalid skim options");
+
+        // perform fuzzy search through history
+        let (test_tx, test_rx) = unbounded();
+        for entry in entries {
+            let item: Arc<dyn SkimItem> = Arc::new(entry);
+            test_tx.send(item)?;
+        }
+        drop(test_tx);
 
-                let selected_items = search_result.selected_items;
-                if selected_items.is_empty() {
-                    tracing::warn!("no tests selected");
-                    return Ok(ExitCode::SUCCESS);
-                }
+        let search_result = skim::Skim::run_with(&skim_options, Some(test_rx))
+            .ok_or_else(|| eyre::eyre!("performing interactive search"))?;
 
-                let test = selected_items[0].text();
-                println!("{}", test);
-                Ok(ExitCode::SUCCESS)
-            }
+        if !search_result.is_abort && search_result.final_key == Key::Ctrl('g') {
+            scope = scope.toggled();
+            query = search_result.query;
+            continue;
         }
-        None => eyre::bail!("No test history found for path {}", search_root.display()),
+        break search_result;
+    };
+
+    if search_result.is_abort {
+        tracing::info!("search aborted");
+        return Ok(SearchOutcome::Aborted.exit_code());
     }
-}
 
-fn main() -> eyre::Result<ExitCode> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .with_writer(io::stderr)
-        .init();
-    color_eyre::install()?;
+    let selected_items = search_result.selected_items;
+    if selected_items.is_empty() {
+        tracing::warn!("no tests selected");
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    let args = Args::parse();
-    // if we need to generate completions, do that early since we don't need to build the state/cache etc.
-    // which fails if we build a nix pkackage
-    if let Some(Command::Completion { shell }) = args.command {
-        return generate_completions(shell);
-    };
+    let selected = &selected_items[0];
+    match selected.as_any().downcast_ref::<TestHistoryEntry>() {
+        Some(entry) => println!("{}", complete_node_id(entry)),
+        None => println!("{}", selected.output()),
+    }
+    Ok(ExitCode::SUCCESS)
+}
 
-    let cache_root = dirs::cache_dir()
-        .map(|p| p.join("testsearch"))
-        .ok_or_else(|| eyre::eyre!("locating cache dir on system"))?;
-    tracing::debug!(cache_root = %cache_root.display(), "using cache root dir");
-    let mut state = State::new(cache_root).wrap_err("constructing persistent state")?;
-    state.migrate_settings().wrap_err("migrating settings")?;
+/// The node id to print for a history entry
+///
+/// Caches from before node ids were recorded in full may hold a bare test name, which pytest
+/// cannot run. Such an entry is looked up among the tests in the directory it was run from, and
+/// replaced by the node id of the only test with that name. Otherwise it is printed unchanged,
+/// with a warning.
+fn complete_node_id(entry: &TestHistoryEntry) -> TestId {
+    let node_id = &entry.node_id;
+    // a bare name parses as a node id of a whole file, so check that it is not one
+    if node_id.name.is_some() || entry.directory.join(&node_id.file).exists() {
+        return node_id.clone();
+    }
+    let bare_name = node_id.to_string();
 
-    let colour = get_colour().context("getting colour from system")?;
-    let skim_options = SkimOptionsBuilder::default()
-        .multi(false)
-        .color(colour)
-        .build()
-        .expect("invalid skim options");
+    let progress = ProgressBar::hidden();
+    let options = ParseOptions {
+        // keep the node ids relative to the directory, like those stored in the history
+        rootdir: Some(&entry.directory),
+        ..Default::default()
+    };
+    let candidates: Vec<TestId> = if entry.directory.is_dir() {
+        collect_tests(
+            collect_test_paths(vec![entry.directory.clone()], None, &progress),
+            &options,
+            &progress,
+        )
+        .into_iter()
+        .filter_map(|test| {
+            test.as_any()
+                .downcast_ref::<TestCase>()
+                .filter(|test_case| test_case.item_name() == bare_name)
+                .map(TestCase::id)
+        })
+        .collect()
+    } else {
+        Vec::new()
+    };
 
-    match args.command {
-        Some(Command::Search(args)) => {
-            match perform_search(args, &skim_options, &mut state)? {
-                Some(_) => Ok(ExitCode::SUCCESS),
-                None => Ok(ExitCode::SUCCESS), // No test selected is not an error
-            }
+    match candidates.as_slice() {
+        [candidate] => {
+            tracing::info!(entry = %node_id, node_id = %candidate, "resolved legacy history entry");
+            candidate.clone()
         }
-        Some(Command::Grep {
-            pattern,
-            run,
-            search_args,
-        }) => {
-            perform_grep_search(pattern, search_args, run)?;
-            Ok(ExitCode::SUCCESS)
+        _ => {
+            eprintln!(
+                "warning: history entry {node_id} is not a full node id, and {} tests in {} have that name",
+                candidates.len(),
+                entry.directory.display()
+            );
+            node_id.clone()
         }
-        Some(Command::Repl { command }) => run_repl(state, skim_options, command),
-        Some(Command::State { state_command }) => match state_command {
-            StateCommand::Clear { all } => {
-                let cache_clear_option = if all {
-                    CacheClearOption::All
-                } else {
-                    CacheClearOption::Current
-                };
-                state
-                    .clear(cache_clear_option)
-                    .wrap_err("clearing cache state")?;
-                Ok(ExitCode::SUCCESS)
-            }
-            StateCommand::Show { all } => {
-                let contents = if all {
-                    serde_json::to_string_pretty(&state.persisted)
-                        .wrap_err("serializing state to JSON")?
-                } else {
-                    let current_dir = current_dir().wrap_err("getting current directory")?;
-                    if let Some(tests) = state.persisted.history(&current_dir) {
-                        serde_json::to_string_pretty(&tests)
-                            .wrap_err("serializing state to JSON")?
-                    } else {
-                        String::new()
-                    }
-                };
-                println!("{contents}");
-                Ok(ExitCode::SUCCESS)
-            }
-        },
-        Some(Command::Rerun { root, last }) => rerun_test(root, last, &state, &skim_options),
-        None => {
-            // Assume search command
-            let search_args = args.search.unwrap_or_default();
-            match perform_search(search_args, &skim_options, &mut state)? {
-                Some(_) => Ok(ExitCode::SUCCESS),
-                None => Ok(ExitCode::SUCCESS), // No test selected is not an error
-            }
+    }
+}
+
+/// The history entries for `scope`, oldest first within each directory
+fn history_entries(state: &State, root: &Path, scope: HistoryScope) -> Vec<TestHistoryEntry> {
+    match scope {
+        HistoryScope::Directory => state
+            .persisted
+            .history(root)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|test| parse_history_entry(test))
+            .map(|test| TestHistoryEntry::new(test, root))
+            .collect(),
+        HistoryScope::Pinned => state
+            .persisted
+            .pinned
+            .get(root)
+            .into_iter()
+            .flatten()
+            .filter_map(|test| parse_history_entry(test))
+            .map(|test| TestHistoryEntry::new(test, root))
+            .collect(),
+        HistoryScope::Global => {
+            // flatten the history from every directory, keeping the directory as context
+            let mut history: Vec<_> = state.persisted.test_history.iter().flatten().collect();
+            history.sort_by_key(|(dir, _)| *dir);
+
+            history
+                .into_iter()
+                .flat_map(|(dir, tests)| {
+                    tests
+                        .iter()
+                        .filter_map(|test| parse_history_entry(test))
+                        .map(|test| TestHistoryEntry::with_directory(test, dir))
+                })
+                .collect()
         }
-        Some(Command::Completion { .. }) => unreachable!("handled above"),
     }
 }
 
-struct TestHistoryEntry {
-    text: String,
+/// Parse a node id stored in the history, skipping it if it is not valid
+fn parse_history_entry(node_id: &str) -> Option<TestId> {
+    node_id
+        .parse()
+        .inspect_err(|e| tracing::warn!(node_id, error = %e, "skipping invalid history entry"))
+        .ok()
 }
 
-impl SkimItem for TestHistoryEntry {
-    fn text(&self) -> std::borrow::Cow<'_, str> {
-        Cow::Borrowed(&self.text)
+/// Functions defined in a file, as their enclosing classes and name
+type DefinedFunctions = HashSet<(Vec<String>, String)>;
+
+/// The functions defined in the Python file at `path`, as their enclosing classes and name, or
+/// `None` if the file cannot be read or has syntax errors, so some may be missing
+///
+/// Every function counts, not just those matching the default test patterns, so tests found with
+/// `--class-pattern`, `--function-pattern` or `--deep` are included.
+fn defined_functions(path: &Path) -> Option<DefinedFunctions> {
+    let bytes = fs::read(path).ok()?;
+    if has_syntax_error(bytes).unwrap_or(true) {
+        return None;
     }
-}
 
-struct Visitor<'s> {
-    filename: &'s Path,
-    sender: &'s mut skim::prelude::Sender<Arc<dyn SkimItem>>,
-    bytes: Vec<u8>,
-    regex: Option<&'s Regex>,
+    let any_name = Regex::new("").expect("empty regex is valid");
+    let options = ParseOptions {
+        class_pattern: Some(&any_name),
+        function_pattern: Some(&any_name),
+        deep: true,
+        ..Default::default()
+    };
+    let (mut test_tx, test_rx) = unbounded();
+    parse_file_with_options(&mut test_tx, path, &options).ok()?;
+    drop(test_tx);
+    Some(
+        test_rx
+            .try_iter()
+            .filter_map(|test| {
+                let test_case = test.as_any().downcast_ref::<TestCase>()?;
+                Some((test_case.classes.clone(), test_case.name.clone()))
+            })
+            .collect(),
+    )
 }
 
-impl<'s> Visitor<'s> {
-    pub fn new(
-        filename: &'s Path,
-        sender: &'s mut skim::prelude::Sender<Arc<dyn SkimItem>>,
-        regex: Option<&'s Regex>,
-    ) -> eyre::Result<Self> {
-        let bytes = fs::read(filename).wrap_err("reading file")?;
-        Ok(Self {
-            filename,
-            sender,
-            bytes,
-            regex,
-        })
+/// Whether the history entry `node_id`, recorded in `dir`, names a test which is known to be gone
+///
+/// That is only the case if its file no longer exists, or the file parses cleanly and defines no
+/// function of that name in those classes. The parameter id is not compared, as parametrized tests
+/// may have been recorded with a larger `--max-parametrize`. Bare names from old caches, and file,
+/// class and notebook entries whose file exists, are never stale.
+fn is_stale_entry(
+    dir: &Path,
+    rootdir: Option<&Path>,
+    node_id: &str,
+    functions: &mut HashMap<PathBuf, Option<DefinedFunctions>>,
+) -> bool {
+    let Some(node_id) = parse_history_entry(node_id) else {
+        return false;
+    };
+    // a bare name parses as a node id of a whole file, without an extension
+    if node_id.name.is_none() && node_id.file.extension().is_none() {
+        return false;
+    }
+    // node ids recorded with `--relative-to-rootdir` are relative to the rootdir instead
+    let Some(path) = [Some(dir), rootdir]
+        .into_iter()
+        .flatten()
+        .map(|base| base.join(&node_id.file))
+        .find(|path| path.exists())
+    else {
+        return true;
+    };
+    let Some(name) = node_id.name else {
+        return false;
+    };
+    // classes are named in CamelCase, and an entry for one is kept while its file exists
+    if name.starts_with(char::is_uppercase) || path.extension().is_none_or(|ext| ext != "py") {
+        return false;
     }
 
-    fn visit(&mut self) -> eyre::Result<()> {
-        let language = tree_sitter_python::LANGUAGE;
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&language.into())
-            .wrap_err("configuring language")?;
-
-        let tree = parser
-            .parse(&self.bytes, None)
-            .ok_or_else(|| eyre::eyre!("parsing file"))?;
-
-        let root = tree.root_node();
-
-        let mut cursor = root.walk();
-        for child in root.children(&mut cursor) {
-            match child.kind() {
-                "decorated_definition" => self.handle_decorated_definition(child, None)?,
-                "class_definition" => self.handle_class_definition(child, None)?,
-                "function_definition" => self.handle_function_definition(child, None)?,
-                "import_statement"
-                | "import_from_statement"
-                | "future_import_statement"
-                | "expression_statement"
-                | "comment"
-                | "if_statement"
-                | "try_statement"
-                | "assert_statement" => continue,
-                kind => todo!("{kind} for file {}", self.filename.display()),
+    let path = fs::canonicalize(&path).unwrap_or(path);
+    let Some(defined) = functions
+        .entry(path)
+        .or_insert_with_key(|path| defined_functions(path))
+    else {
+        return false;
+    };
+    !defined.contains(&(node_id.classes, name))
+}
+
+/// Remove history entries whose tests can no longer be found
+///
+/// See [`is_stale_entry`] for which entries are removed; anything which might still run is kept.
+fn prune_history(state: &mut State, dry_run: bool, missing_dirs: bool) -> eyre::Result<ExitCode> {
+    let history = state.persisted.test_history.clone().unwrap_or_default();
+
+    // parse files without holding the cache lock, since this can be slow
+    let mut functions = HashMap::new();
+    let mut stale_tests: HashMap<PathBuf, HashSet<String>> = HashMap::new();
+    let mut stale_dirs = Vec::new();
+    for (dir, tests) in history {
+        if !dir.is_dir() {
+            if missing_dirs {
+                stale_dirs.push(dir);
             }
+            continue;
         }
 
-        Ok(())
+        let rootdir = pytest_rootdir(&dir).ok();
+        let stale: HashSet<String> = tests
+            .into_iter()
+            .filter(|test| is_stale_entry(&dir, rootdir.as_deref(), test, &mut functions))
+            .collect();
+        if !stale.is_empty() {
+            stale_tests.insert(dir, stale);
+        }
     }
 
-    fn handle_decorated_definition(
-        &mut self,
-        node: Node,
-        class_name: Option<String>,
-    ) -> eyre::Result<()> {
-        let mut cursor = node.walk();
-        for child in node.children(&mut cursor) {
-            match child.kind() {
-                "function_definition" => {
-                    self.handle_function_definition(child, class_name.clone())?
-                }
-                "class_definition" => self.handle_class_definition(child, None)?,
-                "decorator" | "comment" => continue,
-                kind => todo!("{kind}"),
-            }
+    let prefix = if dry_run { "would remove" } else { "removing" };
+    for dir in &stale_dirs {
+        println!("{prefix} {}", dir.display());
+    }
+    for (dir, tests) in &stale_tests {
+        for test in tests {
+            println!("{prefix} {}: {test}", dir.display());
         }
-        Ok(())
     }
 
-    fn handle_class_definition(
-        &mut self,
-        node: Node,
-        parent_class_name: Option<String>,
-    ) -> eyre::Result<()> {
-        // TODO: nested classes?
-        let Some(class_name_node) = node.child(1) else {
-            eyre::bail!("no class name found");
-        };
+    if dry_run {
+        return Ok(ExitCode::SUCCESS);
+    }
 
-        if class_name_node.kind() != "identifier" {
-            eyre::bail!(
-                "invalid class name node type, expected 'identifier', got '{}'",
-                class_name_node.kind()
-            );
+    state.update(|persisted| {
+        for dir in &stale_dirs {
+            persisted.remove_tests(dir, |_| true);
         }
-
-        // TODO: can we prevent this clone?
-        let bytes = self.bytes.clone();
-        let mut class_name = class_name_node
-            .utf8_text(&bytes)
-            .wrap_err("reading class name")?
-            .to_string();
-
-        if !class_name.starts_with("Test") {
-            // stop parsing
-            return Ok(());
+        for (dir, stale) in &stale_tests {
+            persisted.remove_tests(dir, |test| stale.contains(test));
         }
+        Ok(())
+    })?;
 
-        if let Some(parent_class_name) = parent_class_name {
-            if parent_class_name.starts_with("Test") {
-                class_name = format!("{parent_class_name}::{class_name}");
-            }
-        }
+    Ok(ExitCode::SUCCESS)
+}
 
-        let mut cursor = node.walk();
-        for child in node.children(&mut cursor).skip(2) {
-            match child.kind() {
-                "block" => self.handle_class_block(child, Some(class_name.clone()))?,
-                ":" | "argument_list" | "comment" => continue,
-                kind => todo!("{kind}"),
-            }
-        }
+fn main() -> eyre::Result<ExitCode> {
+    let args = Args::parse();
+    QUIET.store(args.quiet, Ordering::Relaxed);
 
-        Ok(())
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(io::stderr);
+    match args.log_format {
+        LogFormat::Human => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
     }
+    color_eyre::install()?;
+    install_panic_hook();
 
-    fn handle_class_block(&mut self, node: Node, class_name: Option<String>) -> eyre::Result<()> {
-        let mut cursor = node.walk();
-        for child in node.children(&mut cursor) {
-            match child.kind() {
-                "decorated_definition" => {
-                    self.handle_decorated_definition(child, class_name.clone())?
-                }
-                "function_definition" => {
-                    self.handle_function_definition(child, class_name.clone())?
-                }
-                "expression_statement" | "comment" | "pass_statement" => continue,
-                "class_definition" => self.handle_class_definition(child, class_name.clone())?,
-                kind => todo!("{kind} {}", node.parent().unwrap().utf8_text(&self.bytes)?),
-            }
-        }
-        Ok(())
+    // like `git -C`, change directory before anything else so that discovery, the cache key
+    // and printed paths all agree on the working directory
+    if let Some(dir) = &args.chdir {
+        std::env::set_current_dir(dir)
+            .wrap_err_with(|| format!("changing directory to {}", dir.display()))?;
+    }
+    // if we need to generate completions, do that early since we don't need to build the state/cache etc.
+    // which fails if we build a nix pkackage
+    match args.command {
+        Some(Command::Completion { shell }) => return generate_completions(shell),
+        Some(Command::Init { shell }) => return generate_init(shell),
+        Some(Command::Version { verbose }) => return print_version(verbose),
+        _ => {}
     }
 
-    fn handle_function_definition(
-        &mut self,
-        node: Node,
-        class_name: Option<String>,
-    ) -> eyre::Result<()> {
-        let Some(identifier_node) = node.child(1) else {
-            eyre::bail!("no identifier node found");
-        };
-
-        let bytes = self.bytes.clone();
-        let identifier = identifier_node
-            .utf8_text(&bytes)
-            .wrap_err("reading bytes for function identifier")?;
+    let cache_root = match args.cache_dir {
+        Some(cache_dir) => cache_dir,
+        None => dirs::cache_dir()
+            .map(|p| p.join("testsearch"))
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "locating cache dir on system, pass --cache-dir or set TESTSEARCH_CACHE_DIR"
+                )
+            })?,
+    };
+    tracing::debug!(cache_root = %cache_root.display(), "using cache root dir");
+    let mut state =
+        State::new(cache_root, args.no_history).wrap_err("constructing persistent state")?;
+    state.migrate_settings().wrap_err("migrating settings")?;
 
-        if !identifier.starts_with("test_") {
-            return Ok(());
-        }
+    let color = args.color;
+    let colour = get_colour(color, &mut state).context("getting colour from system")?;
+    let finder = Finder {
+        colour,
+        prompt: args.prompt,
+        header: args.header,
+    };
 
-        // If regex is provided, check if the function body matches the pattern
-        if let Some(regex) = self.regex {
-            let function_text = node.utf8_text(&bytes).wrap_err("reading function body")?;
+    let update_thread = if args.check_updates {
+        start_update_check(&state)
+    } else {
+        None
+    };
+    // the update check is finished however the command ends, including with an error
+    let result = run_command(args.command, args.search, finder, color, &mut state);
+    if args.check_updates {
+        finish_update_check(&mut state, update_thread);
+    }
+    result
+}
 
-            if !regex.is_match(function_text) {
-                return Ok(());
+/// Run the subcommand given on the command line, or search if there is none
+fn run_command(
+    command: Option<Command>,
+    search: Option<SearchArgs>,
+    finder: Finder,
+    color: ColorMode,
+    state: &mut State,
+) -> eyre::Result<ExitCode> {
+    match command.map(Command::with_env_defaults) {
+        Some(Command::Search(args)) => {
+            let outcome = perform_search(args, &finder, color, state)?;
+            Ok(outcome.exit_code())
+        }
+        Some(Command::Browse(args)) => {
+            let outcome = browse_tests(args, state)?;
+            Ok(outcome.exit_code())
+        }
+        Some(Command::Grep(args)) => perform_grep_search(args, state),
+        Some(Command::Fixtures(args)) => perform_fixture_search(args, &finder),
+        Some(Command::Locate { position }) => locate_test(position),
+        Some(Command::ParseStdin { filename }) => parse_stdin(&filename),
+        Some(Command::Diff { old, new }) => diff_collections(&old, &new, color),
+        Some(Command::Collect {
+            output,
+            shard,
+            search_args,
+        }) => {
+            collect_node_ids(search_args, output, shard)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Command::Run {
+            from,
+            command,
+            runner,
+            remote,
+            addopts,
+            fail_fast,
+            output_dir,
+            junit,
+            shuffle,
+        }) => {
+            let options = RunOptions {
+                fail_fast,
+                output_dir,
+                junit,
+                shuffle,
+                remote,
+                unittest_names: false,
+            };
+            run_from_file(&from, command, runner, addopts.as_deref(), &options, state)
+        }
+        Some(Command::Repl {
+            command,
+            runner,
+            remote,
+            addopts,
+            confirm,
+        }) => {
+            let (command, unittest_names) = match command {
+                Some(command) => (command, false),
+                None => {
+                    let root = current_dir()?;
+                    let (command, unittest_names) = default_run_command(&root, || {
+                        let progress = progress_spinner();
+                        let files = collect_test_paths(vec![root.clone()], None, &progress);
+                        let tests: Vec<_> =
+                            collect_tests(files, &ParseOptions::default(), &progress)
+                                .into_iter()
+                                .collect();
+                        progress.finish_and_clear();
+                        unittest_only(&tests)
+                    });
+                    if !is_quiet() {
+                        eprintln!("Using inferred command template: {command}");
+                    }
+                    (command, unittest_names)
+                }
+            };
+            let command =
+                build_command_template(command, runner, addopts.as_deref(), remote.as_ref());
+            run_repl(
+                state,
+                finder,
+                color,
+                command,
+                unittest_names,
+                remote,
+                confirm,
+            )
+        }
+        Some(Command::State { state_command }) => match state_command {
+            StateCommand::Clear {
+                all,
+                path,
+                older_than,
+            } => {
+                let cache_clear_option = match (all, path, older_than) {
+                    (true, _, _) => CacheClearOption::All,
+                    (false, _, Some(max_age)) => CacheClearOption::OlderThan(max_age),
+                    (false, Some(path), None) => CacheClearOption::Path(path),
+                    (false, None, None) => CacheClearOption::Current,
+                };
+                state
+                    .clear(cache_clear_option)
+                    .wrap_err("clearing cache state")?;
+                Ok(ExitCode::SUCCESS)
+            }
+            StateCommand::Show {
+                all,
+                json: _,
+                plain,
+                durations,
+            } => {
+                let mode = if all {
+                    ShowMode::All
+                } else if plain {
+                    ShowMode::Plain
+                } else if durations {
+                    ShowMode::Durations
+                } else {
+                    ShowMode::Tests
+                };
+                print!("{}", state.show(mode)?);
+                Ok(ExitCode::SUCCESS)
             }
+            StateCommand::Prune {
+                dry_run,
+                missing_dirs,
+            } => prune_history(state, dry_run, missing_dirs),
+        },
+        Some(Command::Rerun {
+            root,
+            last,
+            global,
+            pinned,
+        }) => {
+            let scope = if global {
+                HistoryScope::Global
+            } else if pinned {
+                HistoryScope::Pinned
+            } else {
+                HistoryScope::Directory
+            };
+            rerun_test(root, last, scope, state, &finder)
+        }
+        Some(Command::Pin { node_id }) => {
+            if !state.pin(node_id.clone())? {
+                eprintln!("{node_id} is already pinned");
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Command::Unpin { node_id }) => {
+            if state.unpin(&node_id)? {
+                Ok(ExitCode::SUCCESS)
+            } else {
+                eprintln!("{node_id} is not pinned");
+                Ok(ExitCode::FAILURE)
+            }
+        }
+        None => {
+            // Assume search command
+            let search_args = search.unwrap_or_default().with_env_defaults();
+            let outcome = perform_search(search_args, &finder, color, state)?;
+            Ok(outcome.exit_code())
+        }
+        Some(Command::Completion { .. })
+        | Some(Command::Init { .. })
+        | Some(Command::Version { .. }) => {
+            unreachable!("handled above")
         }
-
-        self.emit(identifier, class_name)
-            .wrap_err("sending test case")?;
-
-        Ok(())
     }
+}
 
-    fn emit(
-        &mut self,
-        test_name: impl Into<String>,
-        class_name: Option<String>,
-    ) -> eyre::Result<()> {
-        let test_case = TestCase {
-            name: test_name.into(),
-            file: self.filename.to_path_buf(),
-            class_name,
-        };
-
-        let send_item = Arc::new(test_case);
+/// Start checking for a newer version in the background if the last check was over a day ago
+fn start_update_check(state: &State) -> Option<thread::JoinHandle<Option<String>>> {
+    let check = state.persisted.update_check.clone().unwrap_or_default();
+    check
+        .is_due()
+        .then(|| thread::spawn(update_check::fetch_latest_version))
+}
 
-        self.sender
-            .send(send_item)
-            .wrap_err("sending test case to closed receiver")?;
+/// Store the result of the update check if it has finished, without waiting for it, and print a
+/// hint if a newer version is known
+fn finish_update_check(
+    state: &mut State,
+    update_thread: Option<thread::JoinHandle<Option<String>>>,
+) {
+    if let Some(handle) = update_thread.filter(|handle| handle.is_finished())
+        && let Ok(Some(latest_version)) = handle.join()
+        && let Err(e) = state.update(|persisted| {
+            persisted.update_check = Some(UpdateCheck::finished(latest_version));
+            Ok(())
+        })
+    {
+        tracing::debug!(error = %e, "recording update check result");
+    }
 
-        Ok(())
+    if let Some(hint) = state
+        .persisted
+        .update_check
+        .as_ref()
+        .and_then(UpdateCheck::hint)
+        && !is_quiet()
+    {
+        // on stderr, as stdout may be captured for the selected node id
+        eprintln!("{hint}");
     }
 }
 
-fn parse_file(
-    sender: &mut skim::prelude::Sender<Arc<dyn SkimItem>>,
-    path: &Path,
-) -> eyre::Result<()> {
-    let mut visitor = Visitor::new(path, sender, None).wrap_err("creating visitor")?;
-    visitor.visit().wrap_err("parsing file")?;
-    Ok(())
+struct TestHistoryEntry {
+    /// Text to match against
+    text: String,
+    /// Node id of the test
+    node_id: TestId,
+    /// Directory the test was run from
+    directory: PathBuf,
 }
 
-fn parse_file_with_regex(
-    sender: &mut skim::prelude::Sender<Arc<dyn SkimItem>>,
-    path: &Path,
-    regex: Option<&Regex>,
-) -> eyre::Result<()> {
-    let mut visitor = Visitor::new(path, sender, regex).wrap_err("creating visitor")?;
-    visitor.visit().wrap_err("parsing file")?;
-    Ok(())
-}
+impl TestHistoryEntry {
+    fn new(node_id: TestId, directory: &Path) -> Self {
+        Self {
+            text: node_id.to_string(),
+            node_id,
+            directory: directory.to_path_buf(),
+        }
+    }
 
-#[derive(Debug)]
-struct TestCase {
-    name: String,
-    file: PathBuf,
-    class_name: Option<String>,
+    /// Include the directory the test was run from, for browsing history across directories
+    fn with_directory(node_id: TestId, directory: &Path) -> Self {
+        Self {
+            text: format!("{node_id} ({})", directory.display()),
+            node_id,
+            directory: directory.to_path_buf(),
+        }
+    }
 }
 
-impl skim::SkimItem for TestCase {
+impl SkimItem for TestHistoryEntry {
     fn text(&self) -> std::borrow::Cow<'_, str> {
-        Cow::Owned(format!("{self}"))
+        Cow::Borrowed(&self.text)
     }
-}
 
-impl fmt::Display for TestCase {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if let Some(class_name) = &self.class_name {
-            f.write_str(&format!(
-                "{}::{}::{}",
-                self.file.display(),
-                class_name,
-                self.name
-            ))
-        } else {
-            f.write_str(&format!("{}::{}", self.file.display(), self.name))
-        }
+    fn output(&self) -> std::borrow::Cow<'_, str> {
+        Cow::Owned(self.node_id.to_string())
     }
 }
 
@@ -1249,3 +4813,32 @@ fn generate_completions(shell: clap_complete::Shell) -> eyre::Result<ExitCode> {
     clap_complete::generate(shell, &mut cmd, bin_name, &mut io::stdout());
     Ok(ExitCode::SUCCESS)
 }
+
+fn print_version(verbose: bool) -> eyre::Result<ExitCode> {
+    println!("testsearch {}", env!("CARGO_PKG_VERSION"));
+    if verbose {
+        // dependency versions are read from Cargo.lock by the build script
+        let language: tree_sitter::Language = tree_sitter_python::LANGUAGE.into();
+        println!(
+            "tree-sitter {} (language ABI {})",
+            env!("TESTSEARCH_TREE_SITTER_VERSION"),
+            language.version()
+        );
+        println!(
+            "tree-sitter-python {}",
+            env!("TESTSEARCH_TREE_SITTER_PYTHON_VERSION")
+        );
+        println!("skim {}", env!("TESTSEARCH_SKIM_VERSION"));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn generate_init(shell: InitShell) -> eyre::Result<ExitCode> {
+    let snippet = match shell {
+        InitShell::Bash => include_str!("shell/init.bash"),
+        InitShell::Zsh => include_str!("shell/init.zsh"),
+        InitShell::Fish => include_str!("shell/init.fish"),
+    };
+    print!("{snippet}");
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,1420 @@
+//! Parsing Python source with tree-sitter into the tests (or fixtures) pytest would collect
+
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::{self, Context};
+use regex::Regex;
+use skim::prelude::*;
+use tree_sitter::Node;
+
+use crate::{expression::Expression, test_id::TestId};
+
+/// The path of `file` relative to `rootdir`, as used in node ids, if it is inside the rootdir
+pub fn rootdir_relative(file: &Path, rootdir: &Path) -> Option<PathBuf> {
+    std::path::absolute(file)
+        .ok()?
+        .strip_prefix(rootdir)
+        .ok()
+        .map(Path::to_path_buf)
+}
+
+/// Selects tests for `grep` by matching their source
+pub struct GrepFilter {
+    pub regexes: Vec<Regex>,
+    /// Require every regex to match, rather than any of them
+    pub require_all: bool,
+    /// Select tests that do not match instead
+    pub invert: bool,
+    /// Record the lines that matched
+    pub show_match: bool,
+    /// Which part of the source the regexes are matched against
+    pub scope: GrepScope,
+}
+
+/// Which part of the source `grep` patterns are matched against
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+pub enum GrepScope {
+    /// Match against the body of each test function
+    #[default]
+    Body,
+    /// Match against the whole file, selecting every test in a matching file
+    File,
+}
+
+impl GrepFilter {
+    fn matches(&self, text: &str) -> bool {
+        let matched = if self.require_all {
+            self.regexes.iter().all(|regex| regex.is_match(text))
+        } else {
+            self.regexes.iter().any(|regex| regex.is_match(text))
+        };
+        matched != self.invert
+    }
+
+    /// Find the lines of the function body at `body` within `source` that match any of the
+    /// regexes, where the body starts on the (0-based) row `start_row` of the file
+    fn matched_lines(
+        &self,
+        source: &str,
+        body: Range<usize>,
+        start_row: usize,
+    ) -> Vec<MatchedLine> {
+        let mut lines = BTreeMap::new();
+        for regex in &self.regexes {
+            for m in regex.find_iter(&source[body.clone()]) {
+                let start = body.start + m.start();
+                let row = start_row + source[body.start..start].matches('\n').count();
+                let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+                let line_end = source[start..]
+                    .find('\n')
+                    .map_or(source.len(), |i| start + i);
+                lines.insert(row + 1, &source[line_start..line_end]);
+            }
+        }
+
+        lines
+            .into_iter()
+            .map(|(line_number, text)| MatchedLine {
+                line_number,
+                text: text.to_string(),
+            })
+            .collect()
+    }
+}
+
+thread_local! {
+    /// Python parser for the current thread, so the language is only configured once per
+    /// (rayon worker) thread rather than once per file
+    static PARSER: RefCell<tree_sitter::Parser> = RefCell::new(python_parser());
+}
+
+fn python_parser() -> tree_sitter::Parser {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(&tree_sitter_python::LANGUAGE.into())
+        .expect("incompatible tree-sitter python grammar");
+    parser
+}
+
+/// Options controlling how test files are parsed
+#[derive(Default)]
+pub struct ParseOptions<'a> {
+    /// Only include tests selected by this filter
+    pub filter: Option<&'a GrepFilter>,
+    /// Print a warning for files containing syntax errors
+    pub warn_parse_errors: bool,
+    /// How discovered tests are matched and shown in the fuzzy finder
+    pub style: ItemStyle,
+    /// Collect `@pytest.fixture` functions instead of tests
+    pub fixtures: bool,
+    /// Make node ids relative to this (absolute) pytest rootdir
+    pub rootdir: Option<&'a Path>,
+    /// Only include tests whose name contains this lowercase substring
+    pub name: Option<String>,
+    /// Only include tests whose marks match this expression
+    pub markexpr: Option<&'a Expression>,
+    /// Only include tests whose node ids match this keyword expression
+    pub keyword: Option<&'a Expression>,
+    /// Show parametrized tests with more parameter combinations than this by their bare name,
+    /// defaults to `DEFAULT_MAX_PARAMETRIZE`
+    pub max_parametrize: Option<usize>,
+    /// Seconds each test took when last run, by node id, shown in the fuzzy finder
+    pub durations: Option<&'a HashMap<String, f64>>,
+    /// Descend into classes whose names match this, rather than those starting with `Test`
+    pub class_pattern: Option<&'a Regex>,
+    /// Collect functions whose names match this, rather than those starting with `test_`
+    ///
+    /// The pattern is compiled once when the arguments are parsed; without one, the default is a
+    /// plain prefix check.
+    pub function_pattern: Option<&'a Regex>,
+    /// Descend into compound statements (see [`NESTED_STATEMENTS`]) looking for tests
+    pub deep: bool,
+}
+
+/// Default for `--max-parametrize`
+const DEFAULT_MAX_PARAMETRIZE: usize = 100;
+
+/// How a test is matched and shown in the fuzzy finder
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ItemStyle {
+    /// Fuzzy match against the full node id, rather than just the classes and test name
+    pub match_full: bool,
+    /// Also match against the test's docstring
+    pub match_docstrings: bool,
+    /// Dim the file path shown next to the test name
+    pub dim_path: bool,
+}
+
+/// Names of the `unittest` base classes whose subclasses are searched for tests whatever they are
+/// called, matched against the last part of a dotted base such as `unittest.TestCase`
+///
+/// Bases merely ending in `TestCase`, e.g. a project's own `BaseTestCase`, are not included, as
+/// their ancestry is unknown without importing them.
+const UNITTEST_BASES: &[&str] = &["TestCase", "IsolatedAsyncioTestCase"];
+
+/// Compound statements which `--deep` searches for tests, e.g. tests only defined on some
+/// Python versions
+const NESTED_STATEMENTS: &[&str] = &[
+    "if_statement",
+    "try_statement",
+    "with_statement",
+    "for_statement",
+];
+
+/// Statements in a module or class body which are skipped, as they either cannot define tests
+/// or only do so in ways `--deep` is needed to find
+///
+/// These are skipped silently; anything else the visitor does not handle is skipped too, but
+/// logged by [`Visitor::skip_node`].
+const SKIPPED_STATEMENTS: &[&str] = &[
+    "comment",
+    "expression_statement",
+    "pass_statement",
+    "import_statement",
+    "import_from_statement",
+    "future_import_statement",
+    "assert_statement",
+    "if_statement",
+    "try_statement",
+    "with_statement",
+    "for_statement",
+    "while_statement",
+    "match_statement",
+    "global_statement",
+    "nonlocal_statement",
+    "delete_statement",
+    "raise_statement",
+    "return_statement",
+    "type_alias_statement",
+    // Python 2, which the grammar still parses
+    "print_statement",
+    "exec_statement",
+];
+
+struct Visitor<'s> {
+    filename: &'s Path,
+    sender: &'s mut skim::prelude::Sender<Arc<dyn SkimItem>>,
+    /// The file contents, decoded once so tests can be matched against them as text
+    source: String,
+    options: &'s ParseOptions<'s>,
+    /// For notebooks, the first line of each cell within the concatenated code cells
+    cells: Option<Vec<NotebookCell>>,
+    /// Path of the file relative to the pytest rootdir, if node ids should use it
+    id_file: Option<PathBuf>,
+    /// With `grep --scope file --show-match`, the matches in the whole file, found once and
+    /// shown for every test
+    file_matched_lines: Vec<MatchedLine>,
+}
+
+impl<'s> Visitor<'s> {
+    /// Prepare to visit `bytes`, the contents of `filename`
+    pub fn new(
+        filename: &'s Path,
+        bytes: Vec<u8>,
+        sender: &'s mut skim::prelude::Sender<Arc<dyn SkimItem>>,
+        options: &'s ParseOptions<'s>,
+    ) -> eyre::Result<Self> {
+        let bytes = decode_source(bytes)?;
+        let (source, cells) = if filename.extension().is_some_and(|ext| ext == "ipynb") {
+            let (source, cells) = notebook_source(&bytes).wrap_err("reading notebook cells")?;
+            (source, Some(cells))
+        } else {
+            // `decode_source` only returns UTF-8
+            (String::from_utf8(bytes).wrap_err("decoding file")?, None)
+        };
+        let id_file = options
+            .rootdir
+            .and_then(|rootdir| rootdir_relative(filename, rootdir));
+        Ok(Self {
+            filename,
+            sender,
+            source,
+            options,
+            cells,
+            id_file,
+            file_matched_lines: Vec::new(),
+        })
+    }
+
+    fn visit(&mut self) -> eyre::Result<()> {
+        let tree = PARSER
+            .with_borrow_mut(|parser| parser.parse(&self.source, None))
+            .ok_or_else(|| eyre::eyre!("parsing file"))?;
+
+        let root = tree.root_node();
+
+        if self.options.warn_parse_errors
+            && let Some(error) = first_error(root)
+        {
+            let position = error.start_position();
+            eprintln!(
+                "warning: {}:{}:{}: syntax error, some tests may be missing",
+                self.filename.display(),
+                position.row + 1,
+                position.column + 1
+            );
+        }
+
+        // a file scoped filter selects every test in the file or none of them, so match it once
+        if let Some(filter) = self.options.filter
+            && let GrepScope::File = filter.scope
+        {
+            if !filter.matches(&self.source) {
+                return Ok(());
+            }
+            if filter.show_match {
+                self.file_matched_lines =
+                    filter.matched_lines(&self.source, 0..self.source.len(), 0);
+            }
+        }
+
+        let mut cursor = root.walk();
+        for child in root.children(&mut cursor) {
+            match child.kind() {
+                "decorated_definition" => self.handle_decorated_definition(child, &[], &[])?,
+                "class_definition" => self.handle_class_definition(child, &[], &[])?,
+                "function_definition" => self.handle_function_definition(child, &[], &[])?,
+                kind if self.options.deep && NESTED_STATEMENTS.contains(&kind) => {
+                    self.handle_nested_statement(child, &[], &[])?
+                }
+                kind if SKIPPED_STATEMENTS.contains(&kind) => continue,
+                kind => self.skip_node(kind, child),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Skip a node which cannot be searched for tests, such as the `ERROR` nodes tree-sitter
+    /// produces around syntax errors, or a statement the visitor does not know about
+    fn skip_node(&self, kind: &str, node: Node) {
+        let position = node.start_position();
+        tracing::debug!(
+            file = %self.filename.display(),
+            line = position.row + 1,
+            column = position.column + 1,
+            kind,
+            "skipping node"
+        );
+    }
+
+    /// Handle a decorated function or class, where `markers` are the marks applied to the
+    /// enclosing classes
+    fn handle_decorated_definition(
+        &mut self,
+        node: Node,
+        classes: &[String],
+        markers: &[String],
+    ) -> eyre::Result<()> {
+        let mut cursor = node.walk();
+        let mut markers = markers.to_vec();
+        markers.extend(
+            node.children(&mut cursor)
+                .filter(|child| child.kind() == "decorator")
+                .filter_map(|decorator| self.marker_name(decorator)),
+        );
+
+        let fixture = if self.options.fixtures {
+            node.children(&mut cursor)
+                .filter(|child| child.kind() == "decorator")
+                .find_map(|decorator| self.fixture_decorator(decorator))
+        } else {
+            None
+        };
+
+        for child in node.children(&mut cursor) {
+            match child.kind() {
+                "function_definition" if fixture.is_some() => {
+                    self.handle_fixture_definition(child, fixture.clone().flatten())?
+                }
+                "function_definition" => {
+                    self.handle_function_definition(child, classes, &markers)?
+                }
+                "class_definition" => self.handle_class_definition(child, classes, &markers)?,
+                "decorator" | "comment" => continue,
+                kind => self.skip_node(kind, child),
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle a class definition, where `parents` is the stack of enclosing test classes and
+    /// `markers` the marks applied to the class and its parents
+    fn handle_class_definition(
+        &mut self,
+        node: Node,
+        parents: &[String],
+        markers: &[String],
+    ) -> eyre::Result<()> {
+        let Some(class_name_node) = node.child(1) else {
+            eyre::bail!("no class name found");
+        };
+
+        if class_name_node.kind() != "identifier" {
+            eyre::bail!(
+                "invalid class name node type, expected 'identifier', got '{}'",
+                class_name_node.kind()
+            );
+        }
+
+        let class_name = class_name_node
+            .utf8_text(self.source.as_bytes())
+            .wrap_err("reading class name")?
+            .to_string();
+
+        let is_test_class = match self.options.class_pattern {
+            Some(pattern) => pattern.is_match(&class_name),
+            None => class_name.starts_with("Test"),
+        };
+        // unittest style classes can have any name, as long as they subclass `TestCase`
+        if !is_test_class && !self.is_unittest_class(node) {
+            // stop parsing
+            return Ok(());
+        }
+
+        let mut classes = parents.to_vec();
+        classes.push(class_name);
+
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor).skip(2) {
+            match child.kind() {
+                "block" => self.handle_class_block(child, &classes, markers)?,
+                ":" | "argument_list" | "comment" => continue,
+                kind => self.skip_node(kind, child),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check whether any of the class bases is one of [`UNITTEST_BASES`], however it was imported
+    fn is_unittest_class(&self, node: Node) -> bool {
+        let Some(superclasses) = node.child_by_field_name("superclasses") else {
+            return false;
+        };
+
+        let mut cursor = superclasses.walk();
+        superclasses
+            .named_children(&mut cursor)
+            .filter_map(|base| base.utf8_text(self.source.as_bytes()).ok())
+            .filter_map(|base| base.rsplit('.').next())
+            .any(|name| UNITTEST_BASES.contains(&name))
+    }
+
+    /// Handle the body of a class, or with `--deep` of a compound statement, in `classes`
+    fn handle_class_block(
+        &mut self,
+        node: Node,
+        classes: &[String],
+        markers: &[String],
+    ) -> eyre::Result<()> {
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            match child.kind() {
+                "decorated_definition" => {
+                    self.handle_decorated_definition(child, classes, markers)?
+                }
+                "function_definition" => {
+                    self.handle_function_definition(child, classes, markers)?
+                }
+                "class_definition" => self.handle_class_definition(child, classes, markers)?,
+                kind if self.options.deep && NESTED_STATEMENTS.contains(&kind) => {
+                    self.handle_nested_statement(child, classes, markers)?
+                }
+                kind if SKIPPED_STATEMENTS.contains(&kind) => continue,
+                kind => self.skip_node(kind, child),
+            }
+        }
+        Ok(())
+    }
+
+    /// Look for tests in the blocks of a compound statement, such as both branches of an `if`
+    fn handle_nested_statement(
+        &mut self,
+        node: Node,
+        classes: &[String],
+        markers: &[String],
+    ) -> eyre::Result<()> {
+        let mut cursor = node.walk();
+        for child in node.named_children(&mut cursor) {
+            match child.kind() {
+                "block" => self.handle_class_block(child, classes, markers)?,
+                "elif_clause"
+                | "else_clause"
+                | "except_clause"
+                | "except_group_clause"
+                | "finally_clause" => self.handle_nested_statement(child, classes, markers)?,
+                // conditions, loop targets and context managers cannot define tests
+                _ => continue,
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether `decorator` is `@pytest.fixture` (or `@fixture`), with or without arguments
+    ///
+    /// Returns `Some` for a fixture decorator, containing the name given with `name=` if any.
+    fn fixture_decorator(&self, decorator: Node) -> Option<Option<String>> {
+        let expression = decorator.named_child(0)?;
+        let (function, arguments) = match expression.kind() {
+            "call" => (
+                expression.child_by_field_name("function")?,
+                expression.child_by_field_name("arguments"),
+            ),
+            _ => (expression, None),
+        };
+
+        let function = function.utf8_text(self.source.as_bytes()).ok()?;
+        if function != "fixture" && !function.ends_with(".fixture") {
+            return None;
+        }
+
+        // `@pytest.fixture(name="...")` registers the fixture under a different name
+        let name = arguments.and_then(|arguments| {
+            let mut cursor = arguments.walk();
+            arguments
+                .named_children(&mut cursor)
+                .filter(|argument| argument.kind() == "keyword_argument")
+                .find(|argument| {
+                    argument
+                        .child_by_field_name("name")
+                        .and_then(|name| name.utf8_text(self.source.as_bytes()).ok())
+                        == Some("name")
+                })
+                .and_then(|argument| argument.child_by_field_name("value"))
+                .filter(|value| value.kind() == "string")
+                .and_then(|value| value.utf8_text(self.source.as_bytes()).ok())
+                .map(|value| value.trim_matches(|c| c == '"' || c == '\'').to_string())
+        });
+        Some(name)
+    }
+
+    /// The name of the pytest mark applied by `decorator`, such as `skip` for
+    /// `@pytest.mark.skip(reason="...")`
+    fn marker_name(&self, decorator: Node) -> Option<String> {
+        let expression = decorator.named_child(0)?;
+        let expression = match expression.kind() {
+            "call" => expression.child_by_field_name("function")?,
+            _ => expression,
+        };
+
+        let expression = expression.utf8_text(self.source.as_bytes()).ok()?;
+        let name = expression
+            .strip_prefix("pytest.mark.")
+            .or_else(|| expression.strip_prefix("mark."))?;
+        Some(name.to_string())
+    }
+
+    /// Handle a function decorated as a fixture, optionally registered as `name`
+    fn handle_fixture_definition(&mut self, node: Node, name: Option<String>) -> eyre::Result<()> {
+        let name = match name {
+            Some(name) => name,
+            None => node
+                .child_by_field_name("name")
+                .ok_or_else(|| eyre::eyre!("no identifier node found"))?
+                .utf8_text(self.source.as_bytes())
+                .wrap_err("reading bytes for fixture identifier")?
+                .to_string(),
+        };
+
+        let fixture = Fixture {
+            name,
+            file: self.filename.to_path_buf(),
+            line: node.start_position().row + 1,
+        };
+        self.sender
+            .send(Arc::new(fixture))
+            .wrap_err("sending fixture to closed receiver")?;
+        Ok(())
+    }
+
+    /// Handle a function definition, where `markers` are the marks applied to the function and
+    /// its enclosing classes
+    fn handle_function_definition(
+        &mut self,
+        node: Node,
+        classes: &[String],
+        markers: &[String],
+    ) -> eyre::Result<()> {
+        // only decorated functions can be fixtures
+        if self.options.fixtures {
+            return Ok(());
+        }
+
+        // looked up by field rather than position, since `async` comes before `def`
+        let Some(identifier_node) = node.child_by_field_name("name") else {
+            eyre::bail!("no identifier node found");
+        };
+
+        // take an owned copy of the (short) identifier so we do not hold a borrow of the file
+        // contents while emitting
+        let identifier = identifier_node
+            .utf8_text(self.source.as_bytes())
+            .wrap_err("reading bytes for function identifier")?
+            .to_string();
+
+        let is_test_function = match self.options.function_pattern {
+            Some(pattern) => pattern.is_match(&identifier),
+            None => identifier.starts_with("test_"),
+        };
+        if !is_test_function {
+            return Ok(());
+        }
+
+        if let Some(name) = &self.options.name
+            && !identifier.to_lowercase().contains(name)
+        {
+            return Ok(());
+        }
+
+        if let Some(markexpr) = self.options.markexpr
+            && !markexpr.evaluate(|marker| markers.iter().any(|m| m == marker))
+        {
+            return Ok(());
+        }
+
+        // If a filter is provided, check if the test is selected by it
+        let mut matched_lines = Vec::new();
+        if let Some(filter) = self.options.filter {
+            match filter.scope {
+                GrepScope::Body => {
+                    let range = node.byte_range();
+                    if !filter.matches(&self.source[range.clone()]) {
+                        return Ok(());
+                    }
+                    if filter.show_match {
+                        matched_lines =
+                            filter.matched_lines(&self.source, range, node.start_position().row);
+                    }
+                }
+                // already matched once for the whole file by `visit`
+                GrepScope::File => matched_lines = self.file_matched_lines.clone(),
+            }
+        }
+
+        match self.parametrize_ids(node) {
+            Some(parameter_ids) => {
+                for parameter_id in parameter_ids {
+                    self.emit(
+                        identifier.clone(),
+                        Some(parameter_id),
+                        classes,
+                        markers,
+                        node,
+                        matched_lines.clone(),
+                    )
+                    .wrap_err("sending test case")?;
+                }
+            }
+            None => self
+                .emit(identifier, None, classes, markers, node, matched_lines)
+                .wrap_err("sending test case")?,
+        }
+
+        Ok(())
+    }
+
+    /// The ids pytest generates for the function `node` from its `@pytest.mark.parametrize`
+    /// decorators
+    ///
+    /// Stacked decorators produce every combination of their ids, with the decorator closest to
+    /// the function first, e.g. `test_x[1-a]`. Returns `None` if the test is not parametrized,
+    /// its parameters cannot be read from the source (e.g. they come from a variable), or there
+    /// are more combinations than `--max-parametrize`, in which case the test is shown by its
+    /// bare name.
+    fn parametrize_ids(&self, node: Node) -> Option<Vec<String>> {
+        let definition = node
+            .parent()
+            .filter(|parent| parent.kind() == "decorated_definition")?;
+        let mut cursor = definition.walk();
+        let decorators: Vec<_> = definition
+            .children(&mut cursor)
+            .filter(|child| {
+                child.kind() == "decorator"
+                    && self.marker_name(*child).as_deref() == Some("parametrize")
+            })
+            .collect();
+        if decorators.is_empty() {
+            return None;
+        }
+
+        // decorators apply from the bottom up
+        let id_lists = decorators
+            .into_iter()
+            .rev()
+            .map(|decorator| self.decorator_parametrize_ids(decorator))
+            .collect::<Option<Vec<_>>>()?;
+
+        let max = self
+            .options
+            .max_parametrize
+            .unwrap_or(DEFAULT_MAX_PARAMETRIZE);
+        let combinations = id_lists
+            .iter()
+            .try_fold(1_usize, |count, ids| count.checked_mul(ids.len()))?;
+        if combinations > max {
+            tracing::debug!(
+                file = %self.filename.display(),
+                combinations,
+                "too many parametrize combinations, using the bare test name"
+            );
+            return None;
+        }
+
+        // the first (bottom) decorator varies slowest
+        id_lists.into_iter().reduce(|combined, ids| {
+            combined
+                .iter()
+                .flat_map(|prefix| ids.iter().map(move |id| format!("{prefix}-{id}")))
+                .collect()
+        })
+    }
+
+    /// The ids from a single `@pytest.mark.parametrize(argnames, argvalues, ids=...)` decorator
+    fn decorator_parametrize_ids(&self, decorator: Node) -> Option<Vec<String>> {
+        let call = decorator
+            .named_child(0)
+            .filter(|expression| expression.kind() == "call")?;
+        let arguments = call.child_by_field_name("arguments")?;
+
+        let (mut argnames, mut argvalues, mut ids) = (None, None, None);
+        let mut position = 0;
+        let mut cursor = arguments.walk();
+        for argument in arguments.named_children(&mut cursor) {
+            let (name, value) = match argument.kind() {
+                "comment" => continue,
+                "keyword_argument" => (
+                    argument
+                        .child_by_field_name("name")?
+                        .utf8_text(self.source.as_bytes())
+                        .ok()?,
+                    argument.child_by_field_name("value")?,
+                ),
+                _ => {
+                    position += 1;
+                    match position {
+                        1 => ("argnames", argument),
+                        2 => ("argvalues", argument),
+                        _ => continue,
+                    }
+                }
+            };
+            match name {
+                "argnames" => argnames = Some(value),
+                "argvalues" => argvalues = Some(value),
+                "ids" => ids = Some(value),
+                _ => {}
+            }
+        }
+
+        // `"a, b"` or `("a", "b")`
+        let argnames = argnames?;
+        let argnames: Vec<String> = match argnames.kind() {
+            "string" => self
+                .string_literal(argnames)?
+                .split(',')
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .collect(),
+            "list" | "tuple" => self
+                .literal_items(argnames)?
+                .into_iter()
+                .map(|name| self.string_literal(name))
+                .collect::<Option<_>>()?,
+            _ => return None,
+        };
+
+        // explicit ids, where `None` entries fall back to the generated id
+        let ids: Vec<Option<String>> = match ids {
+            Some(ids) => self
+                .literal_items(ids)?
+                .into_iter()
+                .map(|id| match id.kind() {
+                    "none" => Some(None),
+                    _ => self.string_literal(id).map(Some),
+                })
+                .collect::<Option<_>>()?,
+            None => Vec::new(),
+        };
+
+        let argvalues = self.literal_items(argvalues?)?;
+        if argnames.is_empty() || argvalues.is_empty() {
+            return None;
+        }
+
+        let parameter_ids = argvalues
+            .into_iter()
+            .enumerate()
+            .map(|(index, parameter_set)| {
+                let (values, explicit_id) = self.parameter_set(parameter_set, argnames.len())?;
+                if values.len() != argnames.len() {
+                    return None;
+                }
+                let id = explicit_id
+                    .or_else(|| ids.get(index).cloned().flatten())
+                    .unwrap_or_else(|| {
+                        values
+                            .iter()
+                            .zip(&argnames)
+                            .map(|(value, argname)| self.value_id(*value, argname, index))
+                            .collect::<Vec<_>>()
+                            .join("-")
+                    });
+                Some(id)
+            })
+            .collect::<Option<_>>()?;
+        Some(unique_ids(parameter_ids))
+    }
+
+    /// Split one entry of the parametrize values into its values and any explicit id
+    ///
+    /// Entries are either `pytest.param(*values, id=...)`, a tuple of values when there are
+    /// several argument names, or a bare value.
+    fn parameter_set<'t>(
+        &self,
+        parameter_set: Node<'t>,
+        argument_count: usize,
+    ) -> Option<(Vec<Node<'t>>, Option<String>)> {
+        if parameter_set.kind() == "call" {
+            let function = parameter_set
+                .child_by_field_name("function")?
+                .utf8_text(self.source.as_bytes())
+                .ok()?;
+            if function == "param" || function.ends_with(".param") {
+                let arguments = parameter_set.child_by_field_name("arguments")?;
+                let mut values = Vec::new();
+                let mut id = None;
+                let mut cursor = arguments.walk();
+                for argument in arguments.named_children(&mut cursor) {
+                    match argument.kind() {
+                        "comment" => continue,
+                        "keyword_argument" => {
+                            let name = argument
+                                .child_by_field_name("name")?
+                                .utf8_text(self.source.as_bytes())
+                                .ok()?;
+                            if name == "id" {
+                                id = self.string_literal(argument.child_by_field_name("value")?);
+                            }
+                        }
+                        _ => values.push(argument),
+                    }
+                }
+                return Some((values, id));
+            }
+        }
+
+        if argument_count == 1 {
+            return Some((vec![parameter_set], None));
+        }
+        Some((self.literal_items(parameter_set)?, None))
+    }
+
+    /// The id pytest generates for a single parameter value: the value itself for strings,
+    /// numbers, booleans and `None`, otherwise the argument name and index, e.g. `obj0`
+    fn value_id(&self, value: Node, argname: &str, index: usize) -> String {
+        let literal = match value.kind() {
+            "string" => self.string_literal(value),
+            "integer" | "float" => value
+                .utf8_text(self.source.as_bytes())
+                .ok()
+                .map(str::to_string),
+            // negative numbers
+            "unary_operator"
+                if value
+                    .child_by_field_name("argument")
+                    .is_some_and(|argument| matches!(argument.kind(), "integer" | "float")) =>
+            {
+                value
+                    .utf8_text(self.source.as_bytes())
+                    .ok()
+                    .map(str::to_string)
+            }
+            "true" => Some("True".to_string()),
+            "false" => Some("False".to_string()),
+            "none" => Some("None".to_string()),
+            _ => None,
+        };
+        literal.unwrap_or_else(|| format!("{argname}{index}"))
+    }
+
+    /// The items of a list or tuple literal, skipping comments
+    fn literal_items<'t>(&self, node: Node<'t>) -> Option<Vec<Node<'t>>> {
+        if node.kind() != "list" && node.kind() != "tuple" {
+            return None;
+        }
+        let mut cursor = node.walk();
+        Some(
+            node.named_children(&mut cursor)
+                .filter(|item| item.kind() != "comment")
+                .collect(),
+        )
+    }
+
+    /// The contents of a plain string literal, or `None` for f-strings
+    fn string_literal(&self, node: Node) -> Option<String> {
+        if node.kind() != "string" {
+            return None;
+        }
+        let text = node.utf8_text(self.source.as_bytes()).ok()?;
+        let quote = text.find(['"', '\''])?;
+        let (prefix, literal) = text.split_at(quote);
+        if prefix.contains(['f', 'F']) {
+            return None;
+        }
+        let delimiter = if literal.starts_with("\"\"\"") || literal.starts_with("'''") {
+            &literal[..3]
+        } else {
+            &literal[..1]
+        };
+        literal
+            .strip_prefix(delimiter)?
+            .strip_suffix(delimiter)
+            .map(str::to_string)
+    }
+
+    /// The docstring of the function `node`: its first statement, if that is a plain string
+    fn docstring(&self, node: Node) -> Option<String> {
+        let body = node.child_by_field_name("body")?;
+        let mut cursor = body.walk();
+        let first = body
+            .named_children(&mut cursor)
+            .find(|child| child.kind() != "comment")?;
+        if first.kind() != "expression_statement" {
+            return None;
+        }
+        let docstring = clean_docstring(&self.string_literal(first.named_child(0)?)?);
+        (!docstring.is_empty()).then_some(docstring)
+    }
+
+    /// Infer the framework of the test defined by the function `node`
+    ///
+    /// Methods of a class deriving from `TestCase` are run by unittest, anything else is a
+    /// pytest test.
+    fn framework(&self, node: Node) -> Framework {
+        let mut parent = node.parent();
+        while let Some(candidate) = parent {
+            if candidate.kind() == "class_definition" {
+                return if self.is_unittest_class(candidate) {
+                    Framework::Unittest
+                } else {
+                    Framework::Pytest
+                };
+            }
+            parent = candidate.parent();
+        }
+        Framework::Pytest
+    }
+
+    /// Send the test defined by the function `node`
+    fn emit(
+        &mut self,
+        test_name: impl Into<String>,
+        parameter_id: Option<String>,
+        classes: &[String],
+        markers: &[String],
+        node: Node,
+        matched_lines: Vec<MatchedLine>,
+    ) -> eyre::Result<()> {
+        let position = node.start_position();
+        let line = position.row + 1;
+        let framework = self.framework(node);
+
+        // the test's decorators are part of its definition
+        let definition = node
+            .parent()
+            .filter(|parent| parent.kind() == "decorated_definition")
+            .unwrap_or(node);
+        let lines = definition.start_position().row + 1..=definition.end_position().row + 1;
+
+        // notebook tests are run by nbval one cell at a time
+        let cell = self.cells.as_ref().and_then(|cells| {
+            cells
+                .iter()
+                .take_while(|cell| cell.start_line <= line)
+                .last()
+                .map(|cell| cell.index)
+        });
+
+        let mut test_case = TestCase {
+            name: test_name.into(),
+            file: self.filename.to_path_buf(),
+            classes: classes.to_vec(),
+            markers: markers.to_vec(),
+            parameter_id,
+            line,
+            column: position.column + 1,
+            lines,
+            cell,
+            id_file: self.id_file.clone(),
+            framework,
+            style: self.options.style,
+            matched_lines,
+            last_duration: None,
+            docstring: self.docstring(node),
+        };
+
+        if let Some(durations) = self.options.durations {
+            test_case.last_duration = durations
+                .get(&test_case.to_string())
+                .and_then(|seconds| Duration::try_from_secs_f64(*seconds).ok());
+        }
+
+        if let Some(keyword) = self.options.keyword {
+            let node_id = test_case.to_string().to_lowercase();
+            if !keyword.evaluate(|name| node_id.contains(&name.to_lowercase())) {
+                return Ok(());
+            }
+        }
+
+        let send_item = Arc::new(test_case);
+
+        self.sender
+            .send(send_item)
+            .wrap_err("sending test case to closed receiver")?;
+
+        Ok(())
+    }
+}
+
+/// Make duplicate parameter ids unique the way pytest does, by numbering each occurrence, e.g.
+/// `a0`, `a1`, or `v1_0`, `v1_1` for ids ending in a digit
+fn unique_ids(ids: Vec<String>) -> Vec<String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for id in &ids {
+        *counts.entry(id.as_str()).or_default() += 1;
+    }
+
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    ids.iter()
+        .map(|id| {
+            if counts[id.as_str()] < 2 {
+                return id.clone();
+            }
+            let occurrence = seen.entry(id.as_str()).or_default();
+            let separator = if id.ends_with(|c: char| c.is_ascii_digit()) {
+                "_"
+            } else {
+                ""
+            };
+            let unique = format!("{id}{separator}{occurrence}");
+            *occurrence += 1;
+            unique
+        })
+        .collect()
+}
+
+/// A code cell of a notebook
+pub struct NotebookCell {
+    /// Index of the cell in the notebook, counting all cells as nbval does
+    index: usize,
+    /// 1-based line of the first line of the cell in the concatenated source
+    start_line: usize,
+}
+
+/// Concatenate the code cells of a Jupyter notebook into a single Python source
+///
+/// IPython magics and shell escapes are blanked out so they do not confuse the parser, keeping
+/// line numbers intact.
+fn notebook_source(bytes: &[u8]) -> eyre::Result<(String, Vec<NotebookCell>)> {
+    let notebook: serde_json::Value =
+        serde_json::from_slice(bytes).wrap_err("decoding notebook JSON")?;
+    let cells = notebook
+        .get("cells")
+        .and_then(|cells| cells.as_array())
+        .ok_or_else(|| eyre::eyre!("notebook has no cells"))?;
+
+    let mut source = String::new();
+    let mut code_cells = Vec::new();
+    let mut next_line = 1;
+    for (index, cell) in cells.iter().enumerate() {
+        if cell.get("cell_type").and_then(|kind| kind.as_str()) != Some("code") {
+            continue;
+        }
+
+        // the source is either a single string or a list of lines
+        let cell_source = match cell.get("source") {
+            Some(serde_json::Value::String(text)) => text.clone(),
+            Some(serde_json::Value::Array(lines)) => {
+                lines.iter().filter_map(|line| line.as_str()).collect()
+            }
+            _ => String::new(),
+        };
+
+        code_cells.push(NotebookCell {
+            index,
+            start_line: next_line,
+        });
+        for line in cell_source.lines() {
+            let trimmed = line.trim_start();
+            if !trimmed.starts_with('%') && !trimmed.starts_with('!') {
+                source.push_str(line);
+            }
+            source.push('\n');
+            next_line += 1;
+        }
+        // separate cells so a cell without a trailing newline cannot run into the next one
+        source.push('\n');
+        next_line += 1;
+    }
+
+    Ok((source, code_cells))
+}
+
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Python's names for Latin-1, after lowercasing and replacing `_` with `-`
+const LATIN_1_NAMES: &[&str] = &[
+    "latin-1",
+    "latin1",
+    "iso-8859-1",
+    "iso8859-1",
+    "l1",
+    "cp819",
+];
+
+/// A Python file declares a source encoding which cannot be read
+#[derive(Debug)]
+pub struct UnsupportedEncoding(String);
+
+impl fmt::Display for UnsupportedEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "skipping file with unsupported source encoding {}",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedEncoding {}
+
+/// Turn the contents of a Python file into UTF-8 for the parser
+///
+/// A leading byte order mark is dropped. Files which are valid UTF-8 are used as they are, even
+/// if they declare another encoding, as ASCII-only legacy files often do. Otherwise Latin-1 is
+/// transcoded, and any other declared encoding is unsupported.
+fn decode_source(mut bytes: Vec<u8>) -> Result<Vec<u8>, UnsupportedEncoding> {
+    if bytes.starts_with(UTF8_BOM) {
+        bytes.drain(..UTF8_BOM.len());
+    }
+    if std::str::from_utf8(&bytes).is_ok() {
+        return Ok(bytes);
+    }
+
+    match declared_encoding(&bytes) {
+        Some(encoding) if LATIN_1_NAMES.contains(&encoding.as_str()) => {
+            // every Latin-1 byte is the code point of the same value
+            Ok(bytes
+                .iter()
+                .map(|&byte| char::from(byte))
+                .collect::<String>()
+                .into_bytes())
+        }
+        Some(encoding) if !encoding.starts_with("utf-8") && !encoding.starts_with("utf8") => {
+            Err(UnsupportedEncoding(encoding))
+        }
+        // invalid UTF-8 without a declaration is parsed as before, tree-sitter copes with it
+        _ => Ok(bytes),
+    }
+}
+
+/// The encoding declared by a PEP 263 comment on one of the first two lines, e.g.
+/// `# -*- coding: latin-1 -*-`, normalised like [`LATIN_1_NAMES`]
+fn declared_encoding(bytes: &[u8]) -> Option<String> {
+    bytes.split(|&byte| byte == b'\n').take(2).find_map(|line| {
+        let line = String::from_utf8_lossy(line);
+        let comment = line.trim_start().strip_prefix('#')?;
+        let (_, rest) = comment.split_once("coding")?;
+        let name: String = rest
+            .strip_prefix([':', '='])?
+            .trim_start()
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            .collect();
+        (!name.is_empty()).then(|| name.to_lowercase().replace('_', "-"))
+    })
+}
+
+/// Find the first ERROR or MISSING node in the tree, in source order, preferring the innermost
+/// node so the reported position points at the problem rather than the start of the file
+fn first_error(node: Node) -> Option<Node> {
+    if !node.has_error() {
+        return None;
+    }
+
+    let mut cursor = node.walk();
+    let child_error = node.children(&mut cursor).find_map(first_error);
+    child_error.or_else(|| (node.is_error() || node.is_missing()).then_some(node))
+}
+
+/// Remove the indentation of a docstring, and its leading and trailing blank lines, as
+/// `inspect.cleandoc` does
+fn clean_docstring(docstring: &str) -> String {
+    let mut lines = docstring.lines();
+    let first = lines.next().unwrap_or_default().trim();
+    let rest: Vec<_> = lines.collect();
+    // the first line follows the opening quotes, so its indentation does not count
+    let indent = rest
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or_default();
+
+    let mut cleaned = vec![first];
+    cleaned.extend(
+        rest.iter()
+            .map(|line| line.get(indent..).unwrap_or_default().trim_end()),
+    );
+    let start = cleaned.iter().position(|line| !line.is_empty());
+    let end = cleaned.iter().rposition(|line| !line.is_empty());
+    match (start, end) {
+        (Some(start), Some(end)) => cleaned[start..=end].join("\n"),
+        _ => String::new(),
+    }
+}
+
+pub fn parse_file_with_options(
+    sender: &mut skim::prelude::Sender<Arc<dyn SkimItem>>,
+    path: &Path,
+    options: &ParseOptions,
+) -> eyre::Result<()> {
+    let bytes = fs::read(path).wrap_err("reading file")?;
+    parse_source_with_options(sender, path, bytes, options)
+}
+
+/// Parse `source` as the contents of `filename`, returning the tests it defines in source order
+///
+/// This needs neither the filesystem nor the fuzzy finder, so it suits callers which already
+/// hold the source, such as `parse-stdin` and `locate`.
+pub fn parse_source(source: &[u8], filename: &Path) -> eyre::Result<Vec<TestCase>> {
+    let (mut test_tx, test_rx) = unbounded();
+    parse_source_with_options(
+        &mut test_tx,
+        filename,
+        source.to_vec(),
+        &ParseOptions::default(),
+    )?;
+    drop(test_tx);
+
+    Ok(test_rx
+        .try_iter()
+        .filter_map(|test| test.as_any().downcast_ref::<TestCase>().cloned())
+        .collect())
+}
+
+/// Whether the Python source `bytes` has a syntax error, after which tests may be missing from
+/// what it parses into
+pub fn has_syntax_error(bytes: Vec<u8>) -> eyre::Result<bool> {
+    let source = decode_source(bytes)?;
+    let tree = PARSER
+        .with_borrow_mut(|parser| parser.parse(&source, None))
+        .ok_or_else(|| eyre::eyre!("parsing file"))?;
+    Ok(tree.root_node().has_error())
+}
+
+/// Parse `source` as the contents of `path`, which need not exist on disk
+fn parse_source_with_options(
+    sender: &mut skim::prelude::Sender<Arc<dyn SkimItem>>,
+    path: &Path,
+    source: Vec<u8>,
+    options: &ParseOptions,
+) -> eyre::Result<()> {
+    let mut visitor = Visitor::new(path, source, sender, options).wrap_err("creating visitor")?;
+    visitor.visit().wrap_err("parsing file")?;
+    Ok(())
+}
+
+/// A test found by parsing a file, which pytest would collect as the node id it displays as
+#[derive(Debug, Clone)]
+pub struct TestCase {
+    pub name: String,
+    pub file: PathBuf,
+    /// Enclosing test classes, outermost first
+    pub classes: Vec<String>,
+    /// Names of the pytest marks applied to the test, including those on its enclosing classes
+    pub markers: Vec<String>,
+    /// For parametrized tests, the id pytest gives this set of parameters
+    pub parameter_id: Option<String>,
+    /// 1-based line number of the test definition
+    pub line: usize,
+    /// 1-based column of the test definition
+    pub column: usize,
+    /// 1-based lines spanned by the test definition, including its decorators
+    pub lines: RangeInclusive<usize>,
+    /// For tests in notebooks, the index of the cell defining the test
+    pub cell: Option<usize>,
+    /// Path used for the file in the node id, if it differs from `file`
+    pub id_file: Option<PathBuf>,
+    /// Which framework the test appears to be written for
+    pub framework: Framework,
+    /// How the test is matched and shown in the fuzzy finder
+    pub style: ItemStyle,
+    /// Lines matching the `grep` patterns, if requested
+    pub matched_lines: Vec<MatchedLine>,
+    /// How long the test took the last time testsearch ran it
+    pub last_duration: Option<Duration>,
+    /// The test function's docstring, with its indentation removed
+    pub docstring: Option<String>,
+}
+
+/// The test framework a test appears to be written for, inferred while parsing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framework {
+    /// A plain test function, or a method of a class that does not derive from `TestCase`
+    Pytest,
+    /// A method of a `unittest.TestCase` subclass
+    Unittest,
+}
+
+/// A line of a test that matched a `grep` pattern
+#[derive(Debug, Clone)]
+pub struct MatchedLine {
+    /// 1-based line number within the file
+    pub line_number: usize,
+    pub text: String,
+}
+
+impl TestCase {
+    /// What the test is shown and matched as in the fuzzy finder: the full node id with
+    /// `--match-full`, otherwise its classes and name without the file path
+    fn identifier(&self) -> String {
+        if self.style.match_full {
+            return self.to_string();
+        }
+        let mut text = String::new();
+        for class_name in &self.classes {
+            text.push_str(class_name);
+            text.push_str("::");
+        }
+        text.push_str(&self.item_name());
+        text
+    }
+
+    /// The pytest node id of the test
+    pub fn id(&self) -> TestId {
+        let file = self.id_file.as_ref().unwrap_or(&self.file).clone();
+        // nbval collects each notebook cell as a single item
+        if let Some(cell) = self.cell {
+            return TestId {
+                file,
+                classes: Vec::new(),
+                name: Some(format!("Cell {cell}")),
+                parameter_id: None,
+            };
+        }
+        TestId {
+            file,
+            classes: self.classes.clone(),
+            name: Some(self.name.clone()),
+            parameter_id: self.parameter_id.clone(),
+        }
+    }
+
+    /// The name pytest gives the test item, including any parameter id, e.g. `test_add[1-2]`
+    pub fn item_name(&self) -> Cow<'_, str> {
+        match &self.parameter_id {
+            Some(parameter_id) => Cow::Owned(format!("{}[{parameter_id}]", self.name)),
+            None => Cow::Borrowed(&self.name),
+        }
+    }
+}
+
+impl skim::SkimItem for TestCase {
+    fn text(&self) -> std::borrow::Cow<'_, str> {
+        let mut text = self.identifier();
+        if self.style.match_docstrings
+            && let Some(docstring) = &self.docstring
+        {
+            // keep each item on a single line
+            for word in docstring.split_whitespace() {
+                text.push(' ');
+                text.push_str(word);
+            }
+        }
+        Cow::Owned(text)
+    }
+
+    fn display<'a>(&'a self, context: DisplayContext<'a>) -> AnsiString<'a> {
+        if self.style.match_full {
+            // the matched text includes the docstring, which is not shown
+            if self.style.match_docstrings {
+                return AnsiString::from(self.identifier());
+            }
+            return AnsiString::from(context);
+        }
+
+        // show the test name prominently, with the file path right-aligned as context
+        let name = self.identifier();
+        let path = self.file.display().to_string();
+        // the last run duration is only shown when it can be dimmed, to keep plain output terse
+        let duration = match self.last_duration {
+            Some(duration) if self.style.dim_path => {
+                format!(" ({:.1}s)", duration.as_secs_f64())
+            }
+            _ => String::new(),
+        };
+        let padding = context
+            .container_width
+            .saturating_sub(name.chars().count() + duration.chars().count() + path.chars().count())
+            .max(2);
+        if self.style.dim_path {
+            AnsiString::parse(&format!(
+                "{name}\x1b[2m{duration}\x1b[0m{:padding$}\x1b[2m{path}\x1b[0m",
+                ""
+            ))
+        } else {
+            AnsiString::from(format!("{name}{:padding$}{path}", ""))
+        }
+    }
+
+    fn output(&self) -> std::borrow::Cow<'_, str> {
+        Cow::Owned(format!("{self}"))
+    }
+
+    fn preview(&self, _context: PreviewContext) -> ItemPreview {
+        let mut preview = format!("{self}\n{}:{}", self.file.display(), self.line);
+        if let Some(docstring) = &self.docstring {
+            preview.push_str("\n\n");
+            preview.push_str(docstring);
+        }
+        ItemPreview::Text(preview)
+    }
+}
+
+impl fmt::Display for TestCase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id())
+    }
+}
+
+/// A pytest fixture, found by `testsearch fixtures`
+#[derive(Debug)]
+pub struct Fixture {
+    pub name: String,
+    pub file: PathBuf,
+    /// 1-based line number of the fixture definition
+    pub line: usize,
+}
+
+impl skim::SkimItem for Fixture {
+    fn text(&self) -> std::borrow::Cow<'_, str> {
+        Cow::Owned(format!("{self}"))
+    }
+}
+
+impl fmt::Display for Fixture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}", self.file.display(), self.name)
+    }
+}
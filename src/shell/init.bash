@@ -0,0 +1,14 @@
+# testsearch shell integration for bash
+#
+# Press alt-t to pick a test and insert `pytest <test>` at the cursor.
+__testsearch_widget() {
+    local selected
+    selected="$(testsearch search)" || return
+    [ -n "$selected" ] || return
+    local insert="pytest $selected"
+    READLINE_LINE="${READLINE_LINE:0:$READLINE_POINT}${insert}${READLINE_LINE:$READLINE_POINT}"
+    READLINE_POINT=$((READLINE_POINT + ${#insert}))
+}
+
+bind -m emacs-standard -x '"\et": __testsearch_widget'
+bind -m vi-insert -x '"\et": __testsearch_widget'
@@ -0,0 +1,12 @@
+# testsearch shell integration for fish
+#
+# Press alt-t to pick a test and insert `pytest <test>` at the cursor.
+function __testsearch_widget
+    set -l selected (testsearch search)
+    if test -n "$selected"
+        commandline --insert "pytest $selected"
+    end
+    commandline --function repaint
+end
+
+bind \et __testsearch_widget
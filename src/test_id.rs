@@ -0,0 +1,83 @@
+//! Structured pytest node ids, e.g. `tests/test_foo.py::TestBar::test_baz[1-a]`
+
+use std::{borrow::Cow, fmt, path::PathBuf, str::FromStr};
+
+/// A pytest node id, split into the file, enclosing classes, test name and parameter id
+///
+/// A node id without `::` names a whole file, and has no test name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TestId {
+    pub file: PathBuf,
+    /// Enclosing test classes, outermost first
+    pub classes: Vec<String>,
+    /// The test function, or `None` for a whole file
+    pub name: Option<String>,
+    /// For parametrized tests, the id pytest gives the set of parameters
+    pub parameter_id: Option<String>,
+}
+
+impl TestId {
+    /// The test name with its parameter id, e.g. `test_baz[1-a]`, or `None` for a whole file
+    pub fn item_name(&self) -> Option<Cow<'_, str>> {
+        let name = self.name.as_deref()?;
+        Some(match &self.parameter_id {
+            Some(parameter_id) => Cow::Owned(format!("{name}[{parameter_id}]")),
+            None => Cow::Borrowed(name),
+        })
+    }
+}
+
+impl FromStr for TestId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("empty node id".to_string());
+        }
+        let Some((file, rest)) = s.split_once("::") else {
+            return Ok(Self {
+                file: PathBuf::from(s),
+                classes: Vec::new(),
+                name: None,
+                parameter_id: None,
+            });
+        };
+
+        // parameter ids may contain anything, including `::` and brackets, but class and test
+        // names are identifiers, so the first `[` starts the parameter id
+        let (path, parameter_id) = match rest.find('[') {
+            Some(start) => {
+                let parameter_id = rest[start + 1..]
+                    .strip_suffix(']')
+                    .ok_or_else(|| format!("invalid node id {s}, unterminated parameter id"))?;
+                (&rest[..start], Some(parameter_id.to_string()))
+            }
+            None => (rest, None),
+        };
+
+        let mut classes: Vec<String> = path.split("::").map(str::to_string).collect();
+        if file.is_empty() || classes.iter().any(String::is_empty) {
+            return Err(format!("invalid node id {s}, empty name between `::`"));
+        }
+        let name = classes.pop();
+        Ok(Self {
+            file: PathBuf::from(file),
+            classes,
+            name,
+            parameter_id,
+        })
+    }
+}
+
+impl fmt::Display for TestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        for class_name in &self.classes {
+            write!(f, "::{class_name}")?;
+        }
+        if let Some(item_name) = self.item_name() {
+            write!(f, "::{item_name}")?;
+        }
+        Ok(())
+    }
+}
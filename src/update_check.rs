@@ -0,0 +1,93 @@
+//! Opt-in check for newer releases of testsearch on crates.io, enabled with `--check-updates`
+//!
+//! The check runs in a background thread, at most once a day after one has succeeded, and any
+//! failure is ignored so it can never break or slow down a normal run. The result is cached in
+//! the persisted state, so the hint keeps being shown until the next check.
+
+use std::{
+    process::{Command, Stdio},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// How often crates.io is asked for the latest version
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+const CRATE_API_URL: &str = "https://crates.io/api/v1/crates/testsearch";
+
+const CRATE_PAGE_URL: &str = "https://crates.io/crates/testsearch";
+
+/// The result of the last update check, as stored in the cache
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateCheck {
+    /// When the last successful check finished, in seconds since the Unix epoch
+    pub checked_at: u64,
+    /// Latest stable version found by the last successful check
+    pub latest_version: Option<String>,
+}
+
+impl UpdateCheck {
+    /// Whether enough time has passed since the last check to check again
+    pub fn is_due(&self) -> bool {
+        now().saturating_sub(self.checked_at) >= CHECK_INTERVAL.as_secs()
+    }
+
+    /// The result of a check which found `latest_version` just now
+    pub fn finished(latest_version: String) -> Self {
+        Self {
+            checked_at: now(),
+            latest_version: Some(latest_version),
+        }
+    }
+
+    /// The hint to show if the latest known version is newer than this one
+    pub fn hint(&self) -> Option<String> {
+        let latest = self.latest_version.as_deref()?;
+        let current = env!("CARGO_PKG_VERSION");
+        (parse_version(latest)? > parse_version(current)?).then(|| {
+            format!("testsearch {latest} is available (installed: {current}), see {CRATE_PAGE_URL}")
+        })
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Ask crates.io for the latest stable version, returning `None` on any failure
+///
+/// `curl` is used rather than an HTTP client library, to keep this rarely used feature from
+/// adding to the build.
+pub fn fetch_latest_version() -> Option<String> {
+    let output = Command::new("curl")
+        .args(["--silent", "--fail", "--location", "--max-time", "5"])
+        // crates.io rejects requests without a user agent
+        .args([
+            "--user-agent",
+            concat!("testsearch/", env!("CARGO_PKG_VERSION"), " (update check)"),
+        ])
+        .arg(CRATE_API_URL)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let response: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+    response["crate"]["max_stable_version"]
+        .as_str()
+        .map(str::to_string)
+}
+
+/// Parse a `major.minor.patch` version, ignoring any pre-release or build suffix
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.split(['-', '+']).next()?;
+    let mut parts = version.split('.').map(|part| part.parse().ok());
+    Some((parts.next()??, parts.next()??, parts.next()??))
+}
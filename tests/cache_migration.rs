@@ -0,0 +1,106 @@
+//! Caches written by older (or newer) versions of testsearch are migrated to the current format
+
+mod common;
+
+use common::{fixtures, key};
+
+/// Load `cache` and print it with `state show --all`, returning the migrated cache and the
+/// warnings logged to stderr
+///
+/// `--no-history` keeps the migration in memory, so the cache file is left as written.
+fn migrate(cache: serde_json::Value) -> (serde_json::Value, String) {
+    let output = common::testsearch_with_cache(&fixtures(), &common::cache_dir_with(&cache))
+        .arg("--no-history")
+        .args(["state", "show", "--all"])
+        // migration warnings are logged, which is silent by default
+        .env("RUST_LOG", "warn")
+        .output()
+        .expect("running testsearch");
+    assert!(output.status.success(), "state show failed: {output:?}");
+
+    let migrated = serde_json::from_slice(&output.stdout).expect("state show prints JSON");
+    (
+        migrated,
+        String::from_utf8(output.stderr).expect("utf-8 stderr"),
+    )
+}
+
+#[test]
+fn unversioned_last_test_is_merged_into_the_history() {
+    let here = key(&fixtures());
+    let elsewhere = key(&fixtures().join("nested_dirs"));
+    let (cache, _) = migrate(serde_json::json!({
+        "test_history": { &here: ["test_a.py::test_a", "test_b.py::test_b"] },
+        "last_test": { &here: "test_a.py::test_a", &elsewhere: "test_c.py::test_c" },
+    }));
+
+    assert_eq!(cache["version"], 2);
+    assert_eq!(cache.get("last_test"), None);
+    assert_eq!(
+        cache["test_history"],
+        serde_json::json!({
+            &here: ["test_b.py::test_b", "test_a.py::test_a"],
+            &elsewhere: ["test_c.py::test_c"],
+        })
+    );
+    let mut last_run: Vec<&String> = cache["last_run"][&here]
+        .as_object()
+        .expect("run times of every test in the history")
+        .keys()
+        .collect();
+    last_run.sort();
+    assert_eq!(last_run, ["test_a.py::test_a", "test_b.py::test_b"]);
+}
+
+#[test]
+fn current_cache_is_unchanged() {
+    let here = key(&fixtures());
+    let history = serde_json::json!({ &here: ["test_a.py::test_a"] });
+    let durations = serde_json::json!({ &here: { "test_a.py::test_a": 0.5 } });
+    let last_run = serde_json::json!({ &here: { "test_a.py::test_a": 1_000_000 } });
+    let (cache, stderr) = migrate(serde_json::json!({
+        "version": 2,
+        "test_history": history,
+        "durations": durations,
+        "last_run": last_run,
+    }));
+
+    assert_eq!(cache["version"], 2);
+    assert_eq!(cache["test_history"], history);
+    assert_eq!(cache["durations"], durations);
+    assert_eq!(cache["last_run"], last_run);
+    assert!(!stderr.contains("WARN"), "stderr: {stderr}");
+}
+
+#[test]
+fn newer_cache_is_read_on_a_best_effort_basis() {
+    let here = key(&fixtures());
+    let history = serde_json::json!({ &here: ["test_a.py::test_a"] });
+    let (cache, stderr) = migrate(serde_json::json!({
+        "version": 99,
+        "test_history": history,
+        "written_by_the_future": true,
+    }));
+
+    assert_eq!(cache["version"], 2);
+    assert_eq!(cache["test_history"], history);
+    assert_eq!(cache.get("written_by_the_future"), None);
+    assert!(stderr.contains("newer version"), "stderr: {stderr}");
+}
+
+#[test]
+fn legacy_last_test_in_a_current_cache_is_merged_with_a_warning() {
+    let here = key(&fixtures());
+    let (cache, stderr) = migrate(serde_json::json!({
+        "version": 2,
+        "test_history": { &here: ["test_a.py::test_a"] },
+        "last_test": { &here: "test_b.py::test_b" },
+    }));
+
+    assert_eq!(cache.get("last_test"), None);
+    assert_eq!(
+        cache["test_history"],
+        serde_json::json!({ &here: ["test_a.py::test_a", "test_b.py::test_b"] })
+    );
+    assert!(stderr.contains("legacy last_test"), "stderr: {stderr}");
+}
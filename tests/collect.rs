@@ -0,0 +1,73 @@
+//! `collect` writes every test's node id, optionally only those in one shard
+
+use std::collections::HashSet;
+
+mod common;
+
+/// The sorted node ids written by `collect` with `args`, searching the whole fixtures directory
+fn collect(args: &[&str]) -> Vec<String> {
+    let output = common::testsearch(&common::fixtures())
+        .arg("collect")
+        .args(args)
+        .output()
+        .expect("running testsearch");
+    common::sorted_lines(&output)
+}
+
+#[test]
+fn shards_partition_the_collection() {
+    let everything = collect(&[]);
+    assert!(
+        everything.len() > 10,
+        "too few tests to shard: {everything:?}"
+    );
+
+    for count in [1, 2, 3, 7] {
+        let mut seen = HashSet::new();
+        for index in 1..=count {
+            for node_id in collect(&["--shard", &format!("{index}/{count}")]) {
+                assert!(
+                    seen.insert(node_id.clone()),
+                    "{node_id} is in more than one of {count} shards"
+                );
+            }
+        }
+        let mut union: Vec<String> = seen.into_iter().collect();
+        union.sort();
+        assert_eq!(union, everything, "{count} shards");
+    }
+}
+
+#[test]
+fn parametrized_cases_are_in_sorted_id_order() {
+    let output = common::testsearch(&common::fixture("parametrize"))
+        .arg("collect")
+        .output()
+        .expect("running testsearch");
+    assert!(output.status.success(), "collect failed: {output:?}");
+    let stdout = String::from_utf8(output.stdout).expect("utf-8 stdout");
+
+    // the parameter ids of each parametrized test, in the order they were written
+    let mut cases: Vec<(&str, Vec<&str>)> = Vec::new();
+    for line in stdout.lines() {
+        let Some((test, parameter_id)) = line.split_once('[') else {
+            continue;
+        };
+        let parameter_id = parameter_id
+            .strip_suffix(']')
+            .expect("terminated parameter id");
+        match cases.last_mut() {
+            Some((last, parameter_ids)) if *last == test => parameter_ids.push(parameter_id),
+            _ => cases.push((test, vec![parameter_id])),
+        }
+    }
+
+    let mut tests = HashSet::new();
+    for (test, parameter_ids) in cases {
+        assert!(tests.insert(test), "the cases of {test} are not together");
+        let mut sorted = parameter_ids.clone();
+        sorted.sort();
+        assert_eq!(parameter_ids, sorted, "cases of {test}");
+    }
+    assert!(tests.len() > 5, "too few parametrized tests: {tests:?}");
+}
@@ -0,0 +1,51 @@
+//! `--relative-to-rootdir` with a second search root outside the rootdir, whose files would
+//! otherwise get the same node ids as different files inside it
+
+mod common;
+
+/// Search the `colliding_roots` fixture from `elsewhere`, with `project` as the first root
+fn search(args: &[&str]) -> (Vec<String>, String) {
+    let output = common::testsearch(&common::fixture("colliding_roots/elsewhere"))
+        .args([
+            "search",
+            "--no-fuzzy-selection",
+            "--root",
+            "../project",
+            "--root",
+            "tests",
+        ])
+        .args(args)
+        .output()
+        .expect("running testsearch");
+    (
+        common::sorted_lines(&output),
+        String::from_utf8(output.stderr).expect("utf-8 stderr"),
+    )
+}
+
+#[test]
+fn file_outside_the_rootdir_is_skipped_with_a_warning() {
+    let (node_ids, stderr) = search(&["--relative-to-rootdir"]);
+    assert_eq!(node_ids, ["tests/test_clash.py::test_in_rootdir"]);
+    assert!(
+        stderr.contains("warning: skipping tests/test_clash.py"),
+        "stderr: {stderr}"
+    );
+    assert!(
+        stderr.contains("without --relative-to-rootdir"),
+        "stderr: {stderr}"
+    );
+}
+
+#[test]
+fn paths_as_found_do_not_collide() {
+    let (node_ids, stderr) = search(&[]);
+    assert_eq!(
+        node_ids,
+        [
+            "../project/tests/test_clash.py::test_in_rootdir",
+            "tests/test_clash.py::test_outside_rootdir",
+        ]
+    );
+    assert!(stderr.is_empty(), "stderr: {stderr}");
+}
@@ -0,0 +1,73 @@
+//! Running the `testsearch` binary against the fixtures, shared by the integration tests
+//!
+//! Each test binary only uses some of these helpers.
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The canonical path of `fixtures/`, whose subdirectories are keys in the cache
+pub fn fixtures() -> PathBuf {
+    fs::canonicalize(Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures"))
+        .expect("resolving fixture directory")
+}
+
+/// The canonical path of `name` below `fixtures/`
+pub fn fixture(name: &str) -> PathBuf {
+    fixtures().join(name)
+}
+
+/// `dir` as a cache key
+pub fn key(dir: &Path) -> String {
+    dir.to_str().expect("utf-8 directory").to_string()
+}
+
+/// A new, empty directory below the system temp directory, unique to this test
+pub fn temp_dir() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "testsearch-tests-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("creating temporary directory");
+    dir
+}
+
+/// A new cache directory holding `cache` as its `cache.json`
+pub fn cache_dir_with(cache: &serde_json::Value) -> PathBuf {
+    let cache_dir = temp_dir();
+    fs::write(cache_dir.join("cache.json"), cache.to_string()).expect("writing cache");
+    cache_dir
+}
+
+/// `testsearch` run in `dir` with the cache in `cache_dir`
+pub fn testsearch_with_cache(dir: &Path, cache_dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_testsearch"));
+    command.arg("--cache-dir").arg(cache_dir).arg("-C").arg(dir);
+    command
+}
+
+/// `testsearch` run in `dir` with an empty cache of its own, recording no history
+pub fn testsearch(dir: &Path) -> Command {
+    let mut command = testsearch_with_cache(dir, &temp_dir());
+    command.arg("--no-history");
+    command
+}
+
+/// The lines of `output`, which must have succeeded, sorted
+pub fn sorted_lines(output: &Output) -> Vec<String> {
+    assert!(output.status.success(), "testsearch failed: {output:?}");
+    let mut lines: Vec<String> = String::from_utf8(output.stdout.clone())
+        .expect("utf-8 stdout")
+        .lines()
+        .map(str::to_string)
+        .collect();
+    lines.sort();
+    lines
+}
@@ -0,0 +1,29 @@
+//! An undecodable cache is backed up and replaced, rather than stopping testsearch from running
+
+use std::fs;
+
+mod common;
+
+#[test]
+fn truncated_cache_is_backed_up_and_rewritten() {
+    let cache_dir = common::temp_dir();
+    let truncated = r#"{"version": 2, "test_history": {"/project": ["test_a.py::te"#;
+    fs::write(cache_dir.join("cache.json"), truncated).expect("writing cache");
+
+    let output = common::testsearch_with_cache(&common::fixtures(), &cache_dir)
+        .args(["state", "show", "--all"])
+        .output()
+        .expect("running testsearch");
+    assert!(output.status.success(), "state show failed: {output:?}");
+
+    let cache: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(cache_dir.join("cache.json")).expect("reading cache"),
+    )
+    .expect("the cache is rewritten as valid JSON");
+    assert_eq!(cache["version"], 2);
+    assert!(cache["test_history"].is_null(), "history was kept: {cache}");
+    assert_eq!(
+        fs::read_to_string(cache_dir.join("cache.json.bak")).expect("reading backup"),
+        truncated
+    );
+}
@@ -0,0 +1,79 @@
+//! `TESTSEARCH_ROOTS` and `TESTSEARCH_PATTERNS` stand in for `--root` and `--file-pattern`
+
+use std::env;
+
+mod common;
+
+/// The sorted node ids found from the fixtures directory with `env` set and `args` passed
+fn search_with_env(env: &[(&str, &str)], args: &[&str]) -> Vec<String> {
+    let output = common::testsearch(&common::fixtures())
+        .args(["search", "--no-fuzzy-selection"])
+        .args(args)
+        .env_remove("TESTSEARCH_ROOTS")
+        .env_remove("TESTSEARCH_PATTERNS")
+        .envs(env.iter().copied())
+        .output()
+        .expect("running testsearch");
+    common::sorted_lines(&output)
+}
+
+/// `paths` joined with the platform's separator, as for `PATH`
+fn path_list(paths: &[&str]) -> String {
+    env::join_paths(paths)
+        .expect("joining paths")
+        .into_string()
+        .expect("utf-8 paths")
+}
+
+#[test]
+fn roots_are_read_from_the_environment() {
+    let roots = path_list(&["nested_dirs/level1", "nested_classes"]);
+    assert_eq!(
+        search_with_env(&[("TESTSEARCH_ROOTS", &roots)], &[]),
+        [
+            "nested_classes/test_nested_classes.py::TestOuter::TestMiddle::TestInner::test_inner",
+            "nested_classes/test_nested_classes.py::TestOuter::TestMiddle::test_middle",
+            "nested_classes/test_nested_classes.py::TestOuter::test_after_nested",
+            "nested_classes/test_nested_classes.py::TestOuter::test_outer",
+            "nested_dirs/level1/level2/test_level2.py::test_level2",
+            "nested_dirs/level1/test_level1.py::test_level1",
+        ]
+    );
+}
+
+#[test]
+fn root_flag_takes_precedence_over_the_environment() {
+    assert_eq!(
+        search_with_env(
+            &[("TESTSEARCH_ROOTS", "nested_classes")],
+            &["--root", "nested_dirs/level1/level2"],
+        ),
+        ["nested_dirs/level1/level2/test_level2.py::test_level2"]
+    );
+}
+
+#[test]
+fn file_patterns_are_read_from_the_environment() {
+    let patterns = path_list(&["test_level1.py", "test_top.py"]);
+    assert_eq!(
+        search_with_env(
+            &[("TESTSEARCH_PATTERNS", &patterns)],
+            &["--root", "nested_dirs"]
+        ),
+        [
+            "nested_dirs/level1/test_level1.py::test_level1",
+            "nested_dirs/test_top.py::test_top",
+        ]
+    );
+}
+
+#[test]
+fn file_pattern_flag_takes_precedence_over_the_environment() {
+    assert_eq!(
+        search_with_env(
+            &[("TESTSEARCH_PATTERNS", "test_top.py")],
+            &["--root", "nested_dirs", "--file-pattern", "test_level2.py"],
+        ),
+        ["nested_dirs/level1/level2/test_level2.py::test_level2"]
+    );
+}
@@ -0,0 +1,170 @@
+//! Check the node ids found in each file under `fixtures/` against the expectations written at
+//! the top of the file
+//!
+//! A fixture states what it should produce in a comment block such as
+//!
+//! ```text
+//! # Expected node ids with `--deep`:
+//! #   test_example.py::test_one
+//! #   test_example.py::TestClass::test_two        [slow]
+//! ```
+//!
+//! where the options in backticks (or a bare `--flag` after "with") are passed to `search`,
+//! and anything after the node id on a line is a note for the reader. Each block is checked by
+//! running `testsearch search --no-fuzzy-selection` on the file from its own directory, and
+//! comparing the sorted node ids. Files without such a block are not checked.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+mod common;
+
+const HEADER: &str = "# Expected node ids";
+
+/// The node ids a fixture should produce when searched with `args`
+#[derive(Debug)]
+struct Expectation {
+    args: Vec<String>,
+    node_ids: Vec<String>,
+}
+
+/// Every Python file below `dir`, in a stable order
+fn python_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).expect("reading fixtures directory") {
+        let path = entry.expect("reading fixtures directory entry").path();
+        if path.is_dir() {
+            files.extend(python_files(&path));
+        } else if path.extension().is_some_and(|ext| ext == "py") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Split `args` on whitespace, keeping single-quoted words together without their quotes
+fn split_args(args: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in args.chars() {
+        match c {
+            '\'' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// The options given in an expectation header, after the "# Expected node ids" prefix
+fn header_args(rest: &str) -> Vec<String> {
+    if let Some((_, quoted)) = rest.split_once("with `") {
+        let (args, _) = quoted
+            .split_once('`')
+            .expect("unterminated backticks in expectation header");
+        return split_args(args);
+    }
+    match rest.trim_start().strip_prefix("with --") {
+        Some(args) => {
+            let args = args.split([',', ':']).next().unwrap_or_default();
+            split_args(&format!("--{args}"))
+        }
+        None => Vec::new(),
+    }
+}
+
+fn expectations(source: &str) -> Vec<Expectation> {
+    let mut expectations = Vec::new();
+    let mut lines = source.lines().peekable();
+    while let Some(line) = lines.next() {
+        let Some(rest) = line.strip_prefix(HEADER) else {
+            continue;
+        };
+        let mut node_ids = Vec::new();
+        while let Some(line) = lines.next_if(|line| line.starts_with("#   ")) {
+            if let Some(node_id) = line[1..].split_whitespace().next() {
+                node_ids.push(node_id.to_string());
+            }
+        }
+        node_ids.sort();
+        expectations.push(Expectation {
+            args: header_args(rest),
+            node_ids,
+        });
+    }
+    expectations
+}
+
+/// The sorted node ids found by searching `file` with `args`, from the file's directory
+fn search(file: &Path, args: &[String]) -> Vec<String> {
+    let dir = file
+        .parent()
+        .expect("fixture files have a parent directory");
+    let output = common::testsearch(dir)
+        .args(["search", "--no-fuzzy-selection", "--root"])
+        .arg(file.file_name().expect("fixture files have a name"))
+        .args(args)
+        .output()
+        .expect("running testsearch");
+    assert!(
+        output.status.success(),
+        "testsearch failed for {}: {}",
+        file.display(),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let mut node_ids: Vec<String> = String::from_utf8(output.stdout)
+        .expect("node ids are utf-8")
+        .lines()
+        .map(str::to_string)
+        .collect();
+    node_ids.sort();
+    node_ids
+}
+
+#[test]
+fn fixtures_produce_expected_node_ids() {
+    let fixtures = common::fixtures();
+    let mut checked = 0;
+    let mut failures = Vec::new();
+    for file in python_files(&fixtures) {
+        // some fixtures start with a byte order mark, or are deliberately not UTF-8, in which
+        // case reading them as Latin-1 is enough to find the expectations
+        let source = match String::from_utf8(fs::read(&file).expect("reading fixture")) {
+            Ok(source) => source,
+            Err(e) => e
+                .into_bytes()
+                .iter()
+                .map(|&byte| char::from(byte))
+                .collect(),
+        };
+        let source = source.trim_start_matches('\u{feff}');
+        for expectation in expectations(source) {
+            checked += 1;
+            let found = search(&file, &expectation.args);
+            if found != expectation.node_ids {
+                failures.push(format!(
+                    "{} with {:?}:\n  expected: {:#?}\n  found: {:#?}",
+                    file.display(),
+                    expectation.args,
+                    expectation.node_ids,
+                    found
+                ));
+            }
+        }
+    }
+
+    assert!(checked > 0, "no fixture expectations found");
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
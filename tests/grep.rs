@@ -0,0 +1,62 @@
+//! `grep` selects tests by the contents of their bodies, using `fixtures/grep_bodies`
+
+use std::collections::HashSet;
+
+mod common;
+
+/// The sorted names of the tests printed by `grep` with `args` in the `grep_bodies` fixture
+fn grep(args: &[&str]) -> Vec<String> {
+    let output = common::testsearch(&common::fixture("grep_bodies"))
+        .arg("grep")
+        .args(args)
+        .args(["--root", "test_grep_bodies.py", "--no-fuzzy-selection"])
+        .output()
+        .expect("running testsearch");
+    common::sorted_lines(&output)
+        .iter()
+        .map(|line| {
+            line.strip_prefix("test_grep_bodies.py::")
+                .expect("node ids are in the fixture file")
+                .to_string()
+        })
+        .collect()
+}
+
+#[test]
+fn invert_match_selects_the_complement() {
+    let matching = grep(&[r"requests\.get"]);
+    let inverted = grep(&[r"requests\.get", "--invert-match"]);
+    assert_eq!(matching, ["test_get_and_assert", "test_get_only"]);
+
+    let matching_set: HashSet<&String> = matching.iter().collect();
+    assert!(
+        inverted.iter().all(|test| !matching_set.contains(test)),
+        "{inverted:?} overlaps {matching:?}"
+    );
+    let mut all: Vec<String> = matching.into_iter().chain(inverted).collect();
+    all.sort();
+    assert_eq!(all, grep(&["--invert-match", "no test contains this"]));
+    assert_eq!(all.len(), 4);
+}
+
+#[test]
+fn all_requires_every_pattern() {
+    assert_eq!(
+        grep(&["-e", r"requests\.get", "-e", "assert_called", "--all"]),
+        ["test_get_and_assert"]
+    );
+}
+
+#[test]
+fn any_requires_one_pattern() {
+    let expected = ["test_assert_only", "test_get_and_assert", "test_get_only"];
+    assert_eq!(
+        grep(&["-e", r"requests\.get", "-e", "assert_called", "--any"]),
+        expected
+    );
+    // `--any` is the default
+    assert_eq!(
+        grep(&["-e", r"requests\.get", "-e", "assert_called"]),
+        expected
+    );
+}
@@ -0,0 +1,50 @@
+//! `search --max-depth` stops discovery below a number of directory levels
+
+mod common;
+
+/// The sorted node ids found in the `nested_dirs` fixture with `--max-depth depth`
+fn search_with_max_depth(depth: usize) -> Vec<String> {
+    let output = common::testsearch(&common::fixtures())
+        .args([
+            "search",
+            "--no-fuzzy-selection",
+            "--root",
+            "nested_dirs",
+            "--max-depth",
+        ])
+        .arg(depth.to_string())
+        .output()
+        .expect("running testsearch");
+    common::sorted_lines(&output)
+}
+
+#[test]
+fn depth_one_finds_only_files_directly_in_the_root() {
+    assert_eq!(
+        search_with_max_depth(1),
+        ["nested_dirs/test_top.py::test_top"]
+    );
+}
+
+#[test]
+fn deeper_levels_are_cut_off() {
+    assert_eq!(
+        search_with_max_depth(2),
+        [
+            "nested_dirs/level1/test_level1.py::test_level1",
+            "nested_dirs/test_top.py::test_top"
+        ]
+    );
+}
+
+#[test]
+fn large_depth_finds_everything() {
+    assert_eq!(
+        search_with_max_depth(10),
+        [
+            "nested_dirs/level1/level2/test_level2.py::test_level2",
+            "nested_dirs/level1/test_level1.py::test_level1",
+            "nested_dirs/test_top.py::test_top",
+        ]
+    );
+}
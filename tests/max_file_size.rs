@@ -0,0 +1,44 @@
+//! `--max-file-size` skips large test files, whether they were walked or listed
+
+use std::{io::Write, process::Stdio};
+
+mod common;
+
+/// The sorted node ids found by `search` in the `nested_dirs` fixture with `args`, under a size
+/// limit only `test_top.py` fits in, with `stdin` piped in
+fn search_small_files(args: &[&str], stdin: &str) -> Vec<String> {
+    let mut child = common::testsearch(&common::fixture("nested_dirs"))
+        .args(["search", "--no-fuzzy-selection", "--max-file-size", "26"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("running testsearch");
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(stdin.as_bytes())
+        .expect("writing file list");
+    let output = child.wait_with_output().expect("waiting for testsearch");
+    common::sorted_lines(&output)
+}
+
+#[test]
+fn walked_files_over_the_limit_are_skipped() {
+    assert_eq!(
+        search_small_files(&["--root", "."], ""),
+        ["./test_top.py::test_top"]
+    );
+}
+
+#[test]
+fn listed_files_over_the_limit_are_skipped() {
+    assert_eq!(
+        search_small_files(
+            &["--files-from", "-"],
+            "test_top.py\nlevel1/test_level1.py\nlevel1/level2/test_level2.py\n"
+        ),
+        ["test_top.py::test_top"]
+    );
+}
@@ -0,0 +1,29 @@
+//! A `--root` which does not exist is skipped with a warning, rather than ending the search
+
+mod common;
+
+#[test]
+fn bogus_root_is_skipped_and_good_root_is_searched() {
+    let output = common::testsearch(&common::fixtures())
+        .args(["search", "--no-fuzzy-selection"])
+        .args([
+            "--root",
+            "nested_dirs/level1/level2",
+            "--root",
+            "no_such_dir",
+        ])
+        .output()
+        .expect("running testsearch");
+    assert!(output.status.success(), "search failed: {output:?}");
+
+    let stdout = String::from_utf8(output.stdout).expect("utf-8 stdout");
+    let stderr = String::from_utf8(output.stderr).expect("utf-8 stderr");
+    assert_eq!(
+        stdout,
+        "nested_dirs/level1/level2/test_level2.py::test_level2\n"
+    );
+    assert!(
+        stderr.contains("warning: search root no_such_dir does not exist, skipping"),
+        "stderr: {stderr}"
+    );
+}
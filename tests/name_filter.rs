@@ -0,0 +1,41 @@
+//! `--name` keeps tests whose name contains a substring, and `--select-1` picks a single survivor
+//! without the fuzzy finder
+
+use std::process::Output;
+
+mod common;
+
+/// Run `search` with `args` from `fixtures/nested_dirs`
+fn search(args: &[&str]) -> Output {
+    common::testsearch(&common::fixture("nested_dirs"))
+        .args(["search", "--root", "level1"])
+        .args(args)
+        .output()
+        .expect("running testsearch")
+}
+
+#[test]
+fn name_filter_is_case_insensitive() {
+    let output = search(&["--no-fuzzy-selection", "--name", "LEVEL2"]);
+    assert!(output.status.success(), "search failed: {output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).expect("utf-8 stdout"),
+        "level1/level2/test_level2.py::test_level2\n"
+    );
+}
+
+#[test]
+fn select_1_selects_the_only_match() {
+    let output = search(&["--name", "level2", "--select-1"]);
+    assert!(output.status.success(), "search failed: {output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).expect("utf-8 stdout"),
+        "level1/level2/test_level2.py::test_level2\n"
+    );
+}
+
+#[test]
+fn select_1_requires_name() {
+    let output = search(&["--select-1"]);
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+}
@@ -0,0 +1,45 @@
+//! `--format` changes how `--no-fuzzy-selection` prints the tests it finds
+
+use std::process::Output;
+
+mod common;
+
+/// Run `search --no-fuzzy-selection` with `args` below `fixtures/nested_dirs/level1`
+fn search(args: &[&str]) -> Output {
+    common::testsearch(&common::fixture("nested_dirs"))
+        .args(["search", "--no-fuzzy-selection", "--root", "level1"])
+        .args(args)
+        .output()
+        .expect("running testsearch")
+}
+
+#[test]
+fn json_is_one_object_per_line() {
+    let output = search(&["--format", "json"]);
+    assert!(output.status.success(), "search failed: {output:?}");
+
+    let stdout = String::from_utf8(output.stdout).expect("utf-8 stdout");
+    let mut node_ids: Vec<String> = stdout
+        .lines()
+        .map(|line| {
+            let test: serde_json::Value = serde_json::from_str(line).expect("each line is JSON");
+            test["node_id"].as_str().expect("node id").to_string()
+        })
+        .collect();
+    node_ids.sort();
+    assert_eq!(
+        node_ids,
+        [
+            "level1/level2/test_level2.py::test_level2",
+            "level1/test_level1.py::test_level1",
+        ]
+    );
+}
+
+#[test]
+fn json_conflicts_with_null() {
+    let output = search(&["--format", "json", "--null"]);
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    let stderr = String::from_utf8(output.stderr).expect("utf-8 stderr");
+    assert!(stderr.contains("cannot be used with '--null'"), "{stderr}");
+}
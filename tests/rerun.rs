@@ -0,0 +1,64 @@
+//! `rerun` with history written by older versions of testsearch
+
+use std::path::Path;
+
+mod common;
+
+/// Run `rerun --last` in `dir` against a cache whose only history entry for `dir` is `entry`
+fn rerun_last(dir: &Path, entry: &str) -> (String, String) {
+    let cache = serde_json::json!({
+        "version": 1,
+        "test_history": { common::key(dir): [entry] },
+    });
+    rerun_last_with_cache(dir, cache)
+}
+
+/// Run `rerun --last` in `dir`, which must be canonical, against `cache`
+fn rerun_last_with_cache(dir: &Path, cache: serde_json::Value) -> (String, String) {
+    let output = common::testsearch_with_cache(dir, &common::cache_dir_with(&cache))
+        .arg("--no-history")
+        .args(["rerun", "--last"])
+        .output()
+        .expect("running testsearch");
+    assert!(output.status.success(), "rerun failed: {output:?}");
+    (
+        String::from_utf8(output.stdout).expect("utf-8 stdout"),
+        String::from_utf8(output.stderr).expect("utf-8 stderr"),
+    )
+}
+
+#[test]
+fn bare_test_name_is_resolved_to_its_node_id() {
+    let (stdout, _) = rerun_last(&common::fixture("nested_classes"), "test_middle");
+    assert_eq!(
+        stdout,
+        "test_nested_classes.py::TestOuter::TestMiddle::test_middle\n"
+    );
+}
+
+#[test]
+fn unknown_bare_name_is_printed_with_a_warning() {
+    let (stdout, stderr) = rerun_last(&common::fixture("nested_classes"), "test_removed");
+    assert_eq!(stdout, "test_removed\n");
+    assert!(stderr.contains("not a full node id"), "stderr: {stderr}");
+}
+
+#[test]
+fn full_node_ids_are_printed_unchanged() {
+    let node_id = "test_nested_classes.py::TestOuter::test_outer";
+    let (stdout, stderr) = rerun_last(&common::fixture("nested_classes"), node_id);
+    assert_eq!(stdout, format!("{node_id}\n"));
+    assert!(stderr.is_empty(), "stderr: {stderr}");
+}
+
+#[test]
+fn last_test_without_test_history_is_rerun() {
+    let dir = common::fixture("nested_classes");
+    let node_id = "test_nested_classes.py::TestOuter::test_outer";
+    let cache = serde_json::json!({
+        "version": 1,
+        "last_test": { common::key(&dir): node_id },
+    });
+    let (stdout, _) = rerun_last_with_cache(&dir, cache);
+    assert_eq!(stdout, format!("{node_id}\n"));
+}
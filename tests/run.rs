@@ -0,0 +1,184 @@
+//! `run` executes each node id it reads with the command template
+
+use std::{
+    fs,
+    io::Write,
+    process::{Output, Stdio},
+};
+
+mod common;
+
+/// Run `echo {}` with `args` for each of `node_ids`, with `env` set, returning stdout
+fn run_echo(node_ids: &[&str], args: &[&str], env: &[(&str, &str)]) -> String {
+    run_with(node_ids, "echo {}", args, env)
+}
+
+/// Run the command `template` with `args` for each of `node_ids`, with `env` set, returning
+/// stdout
+fn run_with(node_ids: &[&str], template: &str, args: &[&str], env: &[(&str, &str)]) -> String {
+    let output = run_output(node_ids, template, args, env);
+    assert!(output.status.success(), "run failed: {output:?}");
+    String::from_utf8(output.stdout).expect("utf-8 stdout")
+}
+
+/// Run the command `template` with `args` for each of `node_ids`, with `env` set, whether or not
+/// the tests pass
+fn run_output(node_ids: &[&str], template: &str, args: &[&str], env: &[(&str, &str)]) -> Output {
+    let mut child = common::testsearch(&common::fixtures())
+        .args(["run", "--from", "-", "--command", template])
+        .args(args)
+        .env_remove("PYTEST_ADDOPTS")
+        .envs(env.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("running testsearch");
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    for node_id in node_ids {
+        writeln!(stdin, "{node_id}").expect("writing node id");
+    }
+    drop(stdin);
+
+    child.wait_with_output().expect("waiting for testsearch")
+}
+
+#[test]
+fn addopts_are_appended_to_the_command() {
+    let stdout = run_echo(&["test_foo.py::test_bar"], &["--addopts", "-x --lf"], &[]);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(
+        lines.contains(&"Executing: echo test_foo.py::test_bar -x --lf"),
+        "stdout: {stdout}"
+    );
+    // the command's own output shows the options reached it
+    assert!(
+        lines.contains(&"test_foo.py::test_bar -x --lf"),
+        "stdout: {stdout}"
+    );
+}
+
+#[test]
+fn failing_tests_fail_the_run_and_are_counted() {
+    let output = run_output(
+        &["test_foo.py::test_bar", "test_foo.py::test_baz"],
+        "test {} = test_foo.py::test_bar",
+        &[],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(1), "output: {output:?}");
+    let stdout = String::from_utf8(output.stdout).expect("utf-8 stdout");
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line == "1 passed, 1 failed, 0 error(s)"),
+        "stdout: {stdout}"
+    );
+}
+
+#[test]
+fn pytest_addopts_is_shown_next_to_the_command() {
+    let stdout = run_echo(&["test_foo.py::test_bar"], &[], &[("PYTEST_ADDOPTS", "-q")]);
+    assert!(
+        stdout.lines().any(|line| line == "With PYTEST_ADDOPTS: -q"),
+        "stdout: {stdout}"
+    );
+}
+
+#[test]
+fn similar_node_ids_are_logged_to_different_files() {
+    let output_dir = common::temp_dir();
+    let node_ids = ["tests/test_foo.py::test_bar", "tests_test_foo.py::test_bar"];
+    run_echo(
+        &node_ids,
+        &["--output-dir", output_dir.to_str().expect("utf-8 temp dir")],
+        &[],
+    );
+
+    let mut logs: Vec<String> = fs::read_dir(&output_dir)
+        .expect("reading output directory")
+        .map(|entry| {
+            let path = entry.expect("directory entry").path();
+            fs::read_to_string(path).expect("reading log")
+        })
+        .collect();
+    logs.sort();
+    assert_eq!(
+        logs,
+        [
+            "tests/test_foo.py::test_bar\n",
+            "tests_test_foo.py::test_bar\n"
+        ]
+    );
+}
+
+#[test]
+fn given_unittest_template_gets_node_ids() {
+    // only the inferred `python -m unittest` template is given dotted names
+    let stdout = run_with(
+        &["tests/test_foo.py::FooTests::test_bar"],
+        "echo -m unittest {}",
+        &[],
+        &[],
+    );
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line == "-m unittest tests/test_foo.py::FooTests::test_bar"),
+        "stdout: {stdout}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn remote_node_ids_reach_the_remote_shell_unchanged() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // stands in for ssh, running the joined command in a shell as the remote host would
+    let bin_dir = common::temp_dir();
+    let ssh = bin_dir.join("ssh");
+    fs::write(&ssh, "#!/bin/sh\nshift 2\nexec sh -c \"$*\"\n").expect("writing fake ssh");
+    fs::set_permissions(&ssh, fs::Permissions::from_mode(0o755)).expect("making ssh executable");
+    let path = std::env::join_paths(std::iter::once(bin_dir).chain(std::env::split_paths(
+        &std::env::var_os("PATH").unwrap_or_default(),
+    )))
+    .expect("joining PATH");
+
+    let remote_dir = common::temp_dir();
+    let remote = format!("host:{}", remote_dir.to_str().expect("utf-8 temp dir"));
+    let node_id = "test_foo.py::test_bar[it's a [glob]*]";
+    let stdout = run_echo(
+        &[node_id],
+        &["--remote", &remote],
+        &[("PATH", path.to_str().expect("utf-8 PATH"))],
+    );
+    assert!(
+        stdout.lines().any(|line| line == node_id),
+        "stdout: {stdout}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupt_wins_over_fail_fast() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // a test command which presses Ctrl-C, as far as testsearch can tell, and then fails
+    let script = common::temp_dir().join("interrupt");
+    fs::write(&script, "#!/bin/sh\nkill -INT $PPID\nsleep 1\nexit 1\n").expect("writing script");
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755))
+        .expect("making script executable");
+
+    let output = run_output(
+        &["test_foo.py::test_bar", "test_foo.py::test_baz"],
+        &format!("{} {{}}", script.to_str().expect("utf-8 temp dir")),
+        &["--fail-fast"],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(130), "output: {output:?}");
+    let stderr = String::from_utf8(output.stderr).expect("utf-8 stderr");
+    assert!(
+        stderr.contains("Interrupted, skipping the remaining 1 test(s)"),
+        "stderr: {stderr}"
+    );
+}
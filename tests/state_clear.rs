@@ -0,0 +1,126 @@
+//! `state clear --path DIR` and `state clear --older-than DURATION` remove part of the state,
+//! wherever they are run from
+
+use std::{
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+mod common;
+
+use common::{fixtures, key};
+
+/// Run `state clear` with `args` from the fixtures directory against `cache`, returning the
+/// cache afterwards and what was printed
+fn clear(cache: serde_json::Value, args: &[&str]) -> (serde_json::Value, String) {
+    let cache_dir = common::cache_dir_with(&cache);
+    let output = common::testsearch_with_cache(&fixtures(), &cache_dir)
+        .args(["state", "clear"])
+        .args(args)
+        .output()
+        .expect("running testsearch");
+    assert!(output.status.success(), "state clear failed: {output:?}");
+
+    let cache = serde_json::from_str(
+        &fs::read_to_string(cache_dir.join("cache.json")).expect("reading cache"),
+    )
+    .expect("cache is JSON");
+    (
+        cache,
+        String::from_utf8(output.stdout).expect("utf-8 stdout"),
+    )
+}
+
+/// Run `state clear --path path` against a cache with history for each of `dirs`, returning the
+/// directories which still have history afterwards
+fn clear_path(dirs: &[&Path], path: &str) -> Vec<String> {
+    let history: serde_json::Map<String, serde_json::Value> = dirs
+        .iter()
+        .map(|dir| (key(dir), serde_json::json!(["test_foo.py::test_foo"])))
+        .collect();
+    let cache = serde_json::json!({ "version": 1, "test_history": history });
+    let (cache, _) = clear(cache, &["--path", path]);
+
+    let mut remaining: Vec<String> = cache["test_history"]
+        .as_object()
+        .expect("history is an object")
+        .keys()
+        .cloned()
+        .collect();
+    remaining.sort();
+    remaining
+}
+
+#[test]
+fn relative_path_is_canonicalized() {
+    let nested_dirs = fixtures().join("nested_dirs");
+    let nested_classes = fixtures().join("nested_classes");
+    let remaining = clear_path(&[&nested_dirs, &nested_classes], "./nested_dirs/level1/..");
+    assert_eq!(remaining, [key(&nested_classes)]);
+}
+
+#[test]
+fn deleted_directory_can_be_cleared() {
+    let deleted = fixtures().join("deleted_project");
+    let nested_dirs = fixtures().join("nested_dirs");
+    let remaining = clear_path(&[&deleted, &nested_dirs], &key(&deleted));
+    assert_eq!(remaining, [key(&nested_dirs)]);
+}
+
+#[test]
+fn older_than_removes_tests_not_run_recently_in_every_directory() {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock after the epoch")
+        .as_secs();
+    let long_ago = now - 60 * 24 * 60 * 60;
+    let here = key(&fixtures());
+    let elsewhere = key(&fixtures().join("nested_dirs"));
+    let cache = serde_json::json!({
+        "version": 2,
+        "test_history": {
+            &here: ["test_old.py::test_old", "test_new.py::test_new", "test_unknown.py::test_unknown"],
+            &elsewhere: ["test_old.py::test_old"],
+        },
+        "durations": {
+            &here: { "test_old.py::test_old": 1.0, "test_batch.py::test_batch": 2.0 },
+        },
+        "last_run": {
+            &here: {
+                "test_old.py::test_old": long_ago,
+                "test_new.py::test_new": now,
+                "test_batch.py::test_batch": long_ago,
+            },
+            &elsewhere: { "test_old.py::test_old": long_ago },
+        },
+    });
+
+    let (cache, stdout) = clear(cache, &["--older-than", "30d"]);
+    assert_eq!(stdout, "Removed 3 test(s) not run in the last 30days\n");
+    assert_eq!(
+        cache["test_history"],
+        serde_json::json!({ &here: ["test_new.py::test_new", "test_unknown.py::test_unknown"] })
+    );
+    assert_eq!(cache.get("durations"), None);
+    assert_eq!(
+        cache["last_run"],
+        serde_json::json!({ &here: { "test_new.py::test_new": now } })
+    );
+}
+
+#[test]
+fn older_than_keeps_history_from_before_run_times_were_recorded() {
+    let here = key(&fixtures());
+    let cache = serde_json::json!({
+        "version": 1,
+        "test_history": { &here: ["test_foo.py::test_foo"] },
+    });
+
+    let (cache, stdout) = clear(cache, &["--older-than", "1h"]);
+    assert_eq!(stdout, "Removed 0 test(s) not run in the last 1h\n");
+    assert_eq!(
+        cache["test_history"],
+        serde_json::json!({ &here: ["test_foo.py::test_foo"] })
+    );
+}
@@ -0,0 +1,60 @@
+//! `state prune` only removes history entries whose tests are known to be gone
+
+mod common;
+
+/// The history entries `state prune --dry-run` would remove from `history`, recorded in the
+/// fixtures directory
+fn would_prune(history: &[&str]) -> Vec<String> {
+    let dir_key = common::key(&common::fixtures());
+    let cache = serde_json::json!({
+        "version": 2,
+        "test_history": { &dir_key: history },
+    });
+    let output =
+        common::testsearch_with_cache(&common::fixtures(), &common::cache_dir_with(&cache))
+            .args(["state", "prune", "--dry-run"])
+            .output()
+            .expect("running testsearch");
+    let prefix = format!("would remove {dir_key}: ");
+    common::sorted_lines(&output)
+        .into_iter()
+        .map(|line| {
+            line.strip_prefix(&prefix)
+                .unwrap_or_else(|| panic!("unexpected output line {line}"))
+                .to_string()
+        })
+        .collect()
+}
+
+#[test]
+fn tests_which_might_still_run_are_kept() {
+    let kept = [
+        // only found with --deep
+        "conditional_tests/test_conditional_tests.py::test_new_python",
+        // only found with custom patterns
+        "naming_conventions/test_naming_conventions.py::DescribeCalculator::should_multiply",
+        // a parameter id beyond the default --max-parametrize
+        "parametrize/test_parametrize.py::test_param_ids[7-8]",
+        "./nested_classes/test_nested_classes.py::TestOuter::test_outer",
+        "nested_classes/test_nested_classes.py::TestOuter",
+        "nested_classes/test_nested_classes.py",
+        // a bare name from an old cache
+        "test_middle",
+        // tests after a syntax error may be missing from what the file parses into
+        "syntax_errors/test_syntax_error.py::test_removed",
+    ];
+    assert_eq!(would_prune(&kept), Vec::<String>::new());
+}
+
+#[test]
+fn tests_which_are_gone_are_removed() {
+    let stale = [
+        "nested_classes/test_nested_classes.py::TestOuter::test_removed",
+        "nested_classes/test_nested_classes.py::test_outer",
+        "nested_classes/test_removed.py",
+        "nested_classes/test_removed.py::test_gone",
+    ];
+    let mut history = stale.to_vec();
+    history.push("nested_classes/test_nested_classes.py::TestOuter::test_outer");
+    assert_eq!(would_prune(&history), stale);
+}
@@ -0,0 +1,101 @@
+//! `state show` prints the recorded tests as a JSON list of node ids, with `--durations` as
+//! objects with their last durations, or with `--plain` one node id per line
+
+mod common;
+
+/// Run `state show` with `args` in the fixtures directory, against a cache holding `history` and
+/// `durations`
+fn state_show(history: &[&str], durations: serde_json::Value, args: &[&str]) -> String {
+    let dir_key = common::key(&common::fixtures());
+    let cache = serde_json::json!({
+        "version": 1,
+        "test_history": { &dir_key: history },
+        "durations": { &dir_key: durations },
+    });
+    let output =
+        common::testsearch_with_cache(&common::fixtures(), &common::cache_dir_with(&cache))
+            .arg("--no-history")
+            .args(["state", "show"])
+            .args(args)
+            .output()
+            .expect("running testsearch");
+    assert!(output.status.success(), "state show failed: {output:?}");
+    String::from_utf8(output.stdout).expect("utf-8 stdout")
+}
+
+#[test]
+fn plain_lists_history_then_timed_tests() {
+    let stdout = state_show(
+        &["test_b.py::test_b", "test_a.py::TestA::test_a"],
+        serde_json::json!({ "test_c.py::test_c": 1.5, "test_b.py::test_b": 0.2 }),
+        &["--plain"],
+    );
+    assert_eq!(
+        stdout,
+        "test_b.py::test_b\ntest_a.py::TestA::test_a\ntest_c.py::test_c\n"
+    );
+}
+
+#[test]
+fn plain_prints_nothing_without_history() {
+    let stdout = state_show(&[], serde_json::json!({}), &["--plain"]);
+    assert_eq!(stdout, "");
+}
+
+#[test]
+fn json_is_the_default() {
+    let history = ["test_b.py::test_b"];
+    let durations = serde_json::json!({ "test_b.py::test_b": 0.5 });
+    let default = state_show(&history, durations.clone(), &[]);
+    let json = state_show(&history, durations, &["--json"]);
+    assert_eq!(default, json);
+
+    let node_ids: serde_json::Value = serde_json::from_str(&json).expect("state show prints JSON");
+    assert_eq!(node_ids, serde_json::json!(["test_b.py::test_b"]));
+}
+
+#[test]
+fn durations_lists_entries_with_their_last_duration() {
+    let stdout = state_show(
+        &["test_b.py::test_b", "test_a.py::test_a"],
+        serde_json::json!({ "test_c.py::test_c": 1.5, "test_b.py::test_b": 0.5 }),
+        &["--durations"],
+    );
+    let entries: serde_json::Value = serde_json::from_str(&stdout).expect("state show prints JSON");
+    assert_eq!(
+        entries,
+        serde_json::json!([
+            { "node_id": "test_b.py::test_b", "last_duration": 0.5 },
+            { "node_id": "test_a.py::test_a", "last_duration": null },
+            { "node_id": "test_c.py::test_c", "last_duration": 1.5 },
+        ])
+    );
+}
+
+#[test]
+fn all_shows_the_whole_migrated_cache() {
+    let stdout = state_show(
+        &["test_b.py::test_b"],
+        serde_json::json!({ "test_c.py::test_c": 1.5 }),
+        &["--all"],
+    );
+    let cache: serde_json::Value = serde_json::from_str(&stdout).expect("state show prints JSON");
+    let dir_key = common::key(&common::fixtures());
+
+    assert_eq!(cache["version"], 2);
+    assert_eq!(
+        cache["test_history"],
+        serde_json::json!({ &dir_key: ["test_b.py::test_b"] })
+    );
+    assert_eq!(
+        cache["durations"],
+        serde_json::json!({ &dir_key: { "test_c.py::test_c": 1.5 } })
+    );
+    let mut last_run: Vec<&String> = cache["last_run"][&dir_key]
+        .as_object()
+        .expect("run times of every recorded test")
+        .keys()
+        .collect();
+    last_run.sort();
+    assert_eq!(last_run, ["test_b.py::test_b", "test_c.py::test_c"]);
+}
@@ -0,0 +1,113 @@
+//! Node ids survive being parsed into a `TestId` and written back out unchanged
+//!
+//! `run --from` parses every node id it reads, and gives each test command the formatted id, so
+//! running `echo {}` prints the result of the round trip.
+
+use std::{io::Write, ops::Range, process::Stdio};
+
+mod common;
+
+/// Run `echo {}` for each of `node_ids`, returning the output and whether testsearch succeeded
+fn echo_node_ids(node_ids: &[String]) -> (Vec<String>, bool) {
+    let mut child = common::testsearch(&common::fixtures())
+        .args(["--quiet", "run", "--from", "-"])
+        .args(["--command", "echo {}"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("running testsearch");
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    for node_id in node_ids {
+        writeln!(stdin, "{node_id}").expect("writing node ids");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("waiting for testsearch");
+    let lines = String::from_utf8(output.stdout)
+        .expect("utf-8 stdout")
+        .lines()
+        .map(|line| line.trim_end_matches('\r').to_string())
+        .collect();
+    (lines, output.status.success())
+}
+
+/// A word of between `lengths.start` and `lengths.end - 1` characters from `alphabet`
+fn random_word(rng: &mut fastrand::Rng, alphabet: &[u8], lengths: Range<usize>) -> String {
+    (0..rng.usize(lengths))
+        .map(|_| char::from(alphabet[rng.usize(..alphabet.len())]))
+        .collect()
+}
+
+/// A random node id, with between zero and three classes and maybe a parameter id
+///
+/// Parameter ids are free text, so they include the `::` and brackets which would confuse naive
+/// splitting. Whitespace is left out, since test commands are split on it.
+fn random_node_id(rng: &mut fastrand::Rng) -> String {
+    const PATH: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789_-/";
+    const IDENTIFIER: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_";
+    const PARAMETER: &[u8] = b"abcxyz0129-_.:[]()/=,'\"\\";
+
+    let directory = random_word(rng, PATH, 1..8);
+    let module = random_word(rng, IDENTIFIER, 1..6);
+    let mut node_id = format!("{directory}/test_{module}.py");
+    // some node ids name a whole file
+    if rng.u8(..10) == 0 {
+        return node_id;
+    }
+    for _ in 0..rng.usize(0..4) {
+        node_id.push_str("::Test");
+        node_id.push_str(&random_word(rng, IDENTIFIER, 1..8));
+    }
+    node_id.push_str("::test_");
+    node_id.push_str(&random_word(rng, IDENTIFIER, 1..12));
+    if rng.bool() {
+        node_id.push('[');
+        node_id.push_str(&random_word(rng, PARAMETER, 0..10));
+        node_id.push(']');
+    }
+    node_id
+}
+
+#[test]
+fn random_node_ids_round_trip() {
+    for seed in 0..4 {
+        let mut rng = fastrand::Rng::with_seed(seed);
+        let node_ids: Vec<String> = (0..50).map(|_| random_node_id(&mut rng)).collect();
+        let (printed, success) = echo_node_ids(&node_ids);
+        assert!(success, "run failed with seed {seed}");
+        assert_eq!(printed, node_ids, "seed {seed}");
+    }
+}
+
+#[test]
+fn tricky_node_ids_round_trip() {
+    let node_ids: Vec<String> = [
+        "test_foo.py",
+        "tests/test_foo.py::test_bar",
+        "/abs/tests/test_foo.py::TestA::TestB::test_bar",
+        "test_foo.py::test_bar[]",
+        "test_foo.py::test_bar[a::b]",
+        "test_foo.py::TestA::test_bar[[1]-x[2]]",
+    ]
+    .into_iter()
+    .map(str::to_string)
+    .collect();
+    let (printed, success) = echo_node_ids(&node_ids);
+    assert!(success);
+    assert_eq!(printed, node_ids);
+}
+
+#[test]
+fn malformed_node_ids_are_rejected() {
+    for node_id in [
+        "test_foo.py::",
+        "::test_bar",
+        "test_foo.py::::test_bar",
+        "test_foo.py::test_bar[1",
+    ] {
+        let (printed, success) = echo_node_ids(&[node_id.to_string()]);
+        assert!(!success, "{node_id} was accepted");
+        assert!(printed.is_empty(), "{node_id} was run: {printed:?}");
+    }
+}
@@ -0,0 +1,40 @@
+//! `--check-updates` shows a hint while a newer version is known
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod common;
+
+/// Run testsearch with `args` and `--check-updates`, with a recent check having found a newer
+/// version, returning stderr
+fn with_newer_version(args: &[&str]) -> String {
+    let checked_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time after the epoch")
+        .as_secs();
+    let cache = serde_json::json!({
+        "version": 2,
+        "update_check": { "checked_at": checked_at, "latest_version": "999.0.0" },
+    });
+    let output =
+        common::testsearch_with_cache(&common::fixtures(), &common::cache_dir_with(&cache))
+            .arg("--check-updates")
+            .args(args)
+            .output()
+            .expect("running testsearch");
+    String::from_utf8(output.stderr).expect("utf-8 stderr")
+}
+
+#[test]
+fn hint_is_shown_when_the_command_fails() {
+    let stderr = with_newer_version(&["locate", "test_missing.py:1"]);
+    assert!(
+        stderr.contains("testsearch 999.0.0 is available"),
+        "stderr: {stderr}"
+    );
+}
+
+#[test]
+fn quiet_hides_the_hint() {
+    let stderr = with_newer_version(&["--quiet", "collect"]);
+    assert!(!stderr.contains("is available"), "stderr: {stderr}");
+}